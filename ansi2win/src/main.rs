@@ -1,7 +1,7 @@
 extern crate winapi;
 extern crate ansi_shim;
 
-use ansi_shim::{Terminal,Color,Style,Shim};
+use ansi_shim::{Terminal,Color,Style,Shim,GraphicState};
 use std::ptr;
 use std::mem;
 use winapi::shared::minwindef::WORD;
@@ -24,17 +24,29 @@ fn main() {
     }
 }
 
+const FOREGROUND_MASK: WORD = FOREGROUND_RED | FOREGROUND_BLUE | FOREGROUND_GREEN | FOREGROUND_INTENSITY;
+const BACKGROUND_MASK: WORD = BACKGROUND_RED | BACKGROUND_BLUE | BACKGROUND_GREEN | BACKGROUND_INTENSITY;
+
 struct WinTerm {
     handle: HANDLE, 
-    bold: bool,
+    /// The console attributes before any styling was applied
+    default_attributes: WORD,
+    state: GraphicState,
 }
 
 impl WinTerm {
     pub fn new() -> Self {
-        Self {
+        let mut term = Self {
             handle: unsafe { GetStdHandle(STD_OUTPUT_HANDLE) },
-            bold: false,
+            default_attributes: FOREGROUND_RED | FOREGROUND_BLUE | FOREGROUND_GREEN,
+            state: GraphicState::new(),
+        };
+
+        if let Some(attrs) = term.get_text_attributes() {
+            term.default_attributes = attrs;
         }
+
+        term
     }
 }
 
@@ -59,6 +71,78 @@ impl WinTerm {
     }
 }
 
+impl WinTerm {
+    fn update_text_attributes(&mut self) {
+        let mut attrs = match self.state.fg_color().to_basic() {
+            Color::Default => self.default_attributes & FOREGROUND_MASK,
+            color => foreground_attributes(color),
+        };
+
+        if self.state.has_style(Style::Bold) {
+            attrs |= FOREGROUND_INTENSITY;
+        }
+
+        attrs |= match self.state.bg_color().to_basic() {
+            Color::Default => self.default_attributes & BACKGROUND_MASK,
+            color => background_attributes(color),
+        };
+
+        if self.state.has_style(Style::Reverse) {
+            attrs |= COMMON_LVB_REVERSE_VIDEO;
+        }
+
+        if self.state.styles().iter().any(|style| style.is_underline()) {
+            attrs |= COMMON_LVB_UNDERSCORE;
+        }
+
+        self.set_text_attributes(attrs);
+    }
+}
+
+fn foreground_attributes(color: Color) -> WORD {
+    match color {
+        Color::Black => 0,
+        Color::Red => FOREGROUND_RED,
+        Color::Green => FOREGROUND_GREEN,
+        Color::Yellow => FOREGROUND_RED | FOREGROUND_GREEN,
+        Color::Blue => FOREGROUND_BLUE,
+        Color::Magenta => FOREGROUND_RED | FOREGROUND_BLUE,
+        Color::Cyan => FOREGROUND_BLUE | FOREGROUND_GREEN,
+        Color::White => FOREGROUND_RED | FOREGROUND_BLUE | FOREGROUND_GREEN,
+        Color::BrightBlack => FOREGROUND_INTENSITY,
+        Color::BrightRed => FOREGROUND_RED | FOREGROUND_INTENSITY,
+        Color::BrightGreen => FOREGROUND_GREEN | FOREGROUND_INTENSITY,
+        Color::BrightYellow => FOREGROUND_RED | FOREGROUND_GREEN | FOREGROUND_INTENSITY,
+        Color::BrightBlue => FOREGROUND_BLUE | FOREGROUND_INTENSITY,
+        Color::BrightMagenta => FOREGROUND_RED | FOREGROUND_BLUE | FOREGROUND_INTENSITY,
+        Color::BrightCyan => FOREGROUND_BLUE | FOREGROUND_GREEN | FOREGROUND_INTENSITY,
+        Color::BrightWhite => FOREGROUND_RED | FOREGROUND_BLUE | FOREGROUND_GREEN | FOREGROUND_INTENSITY,
+        _ => FOREGROUND_RED | FOREGROUND_BLUE | FOREGROUND_GREEN,
+    }
+}
+
+fn background_attributes(color: Color) -> WORD {
+    match color {
+        Color::Black => 0,
+        Color::Red => BACKGROUND_RED,
+        Color::Green => BACKGROUND_GREEN,
+        Color::Yellow => BACKGROUND_RED | BACKGROUND_GREEN,
+        Color::Blue => BACKGROUND_BLUE,
+        Color::Magenta => BACKGROUND_RED | BACKGROUND_BLUE,
+        Color::Cyan => BACKGROUND_BLUE | BACKGROUND_GREEN,
+        Color::White => BACKGROUND_RED | BACKGROUND_BLUE | BACKGROUND_GREEN,
+        Color::BrightBlack => BACKGROUND_INTENSITY,
+        Color::BrightRed => BACKGROUND_RED | BACKGROUND_INTENSITY,
+        Color::BrightGreen => BACKGROUND_GREEN | BACKGROUND_INTENSITY,
+        Color::BrightYellow => BACKGROUND_RED | BACKGROUND_GREEN | BACKGROUND_INTENSITY,
+        Color::BrightBlue => BACKGROUND_BLUE | BACKGROUND_INTENSITY,
+        Color::BrightMagenta => BACKGROUND_RED | BACKGROUND_BLUE | BACKGROUND_INTENSITY,
+        Color::BrightCyan => BACKGROUND_BLUE | BACKGROUND_GREEN | BACKGROUND_INTENSITY,
+        Color::BrightWhite => BACKGROUND_RED | BACKGROUND_BLUE | BACKGROUND_GREEN | BACKGROUND_INTENSITY,
+        _ => 0,
+    }
+}
+
 impl Terminal for WinTerm {
     fn print(&mut self, ch: char) -> io::Result<()> {
         print!("{}", ch);
@@ -66,109 +150,33 @@ impl Terminal for WinTerm {
     }
 
     fn set_fg_color(&mut self, color: Color) -> io::Result<()> {
-        unsafe {
-            let mut attrs = match self.get_text_attributes() {
-                None => return Ok(()),
-                Some(attrs) => attrs,
-            };
-
-            attrs &= !(FOREGROUND_RED | FOREGROUND_BLUE | FOREGROUND_GREEN | FOREGROUND_INTENSITY);
-
-            if self.bold {
-                attrs |= FOREGROUND_INTENSITY;
-            }
-
-            let color = match color {
-                Color::Black => 0,
-                Color::Red => FOREGROUND_RED,
-                Color::Green => FOREGROUND_GREEN,
-                Color::Yellow => FOREGROUND_RED | FOREGROUND_GREEN,
-                Color::Blue => FOREGROUND_BLUE,
-                Color::Magenta => FOREGROUND_RED | FOREGROUND_BLUE,
-                Color::Cyan => FOREGROUND_BLUE | FOREGROUND_GREEN,
-                Color::White => FOREGROUND_RED | FOREGROUND_BLUE | FOREGROUND_GREEN,
-                Color::BrightBlack => FOREGROUND_INTENSITY,
-                Color::BrightRed => FOREGROUND_RED | FOREGROUND_INTENSITY,
-                Color::BrightGreen => FOREGROUND_GREEN | FOREGROUND_INTENSITY,
-                Color::BrightYellow => FOREGROUND_RED | FOREGROUND_GREEN | FOREGROUND_INTENSITY,
-                Color::BrightBlue => FOREGROUND_BLUE | FOREGROUND_INTENSITY,
-                Color::BrightMagenta => FOREGROUND_RED | FOREGROUND_BLUE | FOREGROUND_INTENSITY,
-                Color::BrightCyan => FOREGROUND_BLUE | FOREGROUND_GREEN | FOREGROUND_INTENSITY,
-                Color::BrightWhite => FOREGROUND_RED | FOREGROUND_BLUE | FOREGROUND_GREEN | FOREGROUND_INTENSITY,
-                _ => return Ok(())
-            };
-
-            attrs |= color;
-
-            self.set_text_attributes(attrs);
-
-            Ok(())
-        }
+        self.state.set_fg_color(color);
+        self.update_text_attributes();
+        Ok(())
     }
 
     fn set_bg_color(&mut self, color: Color) -> io::Result<()> {
-        unsafe {
-            let mut attrs = match self.get_text_attributes() {
-                None => return Ok(()),
-                Some(attrs) => attrs,
-            };
-
-            attrs &= !(BACKGROUND_RED | BACKGROUND_BLUE | BACKGROUND_GREEN | BACKGROUND_INTENSITY);
-
-            let color = match color {
-                Color::Black => 0,
-                Color::Red => BACKGROUND_RED,
-                Color::Green => BACKGROUND_GREEN,
-                Color::Yellow => BACKGROUND_RED | BACKGROUND_GREEN,
-                Color::Blue => BACKGROUND_BLUE,
-                Color::Magenta => BACKGROUND_RED | BACKGROUND_BLUE,
-                Color::Cyan => BACKGROUND_BLUE | BACKGROUND_GREEN,
-                Color::White => BACKGROUND_RED | BACKGROUND_BLUE | BACKGROUND_GREEN,
-                Color::BrightBlack => BACKGROUND_INTENSITY,
-                Color::BrightRed => BACKGROUND_RED | BACKGROUND_INTENSITY,
-                Color::BrightGreen => BACKGROUND_GREEN | BACKGROUND_INTENSITY,
-                Color::BrightYellow => BACKGROUND_RED | BACKGROUND_GREEN | BACKGROUND_INTENSITY,
-                Color::BrightBlue => BACKGROUND_BLUE | BACKGROUND_INTENSITY,
-                Color::BrightMagenta => BACKGROUND_RED | BACKGROUND_BLUE | BACKGROUND_INTENSITY,
-                Color::BrightCyan => BACKGROUND_BLUE | BACKGROUND_GREEN | BACKGROUND_INTENSITY,
-                Color::BrightWhite => BACKGROUND_RED | BACKGROUND_BLUE | BACKGROUND_GREEN | BACKGROUND_INTENSITY,
-                _ => return Ok(())
-            };
-
-            attrs |= color;
-
-            self.set_text_attributes(attrs);
-
-            Ok(())
-        }
+        self.state.set_bg_color(color);
+        self.update_text_attributes();
+        Ok(())
     }
 
     fn reset_style(&mut self) -> io::Result<()> {
-        let attrs = FOREGROUND_RED | FOREGROUND_GREEN | FOREGROUND_BLUE | FOREGROUND_INTENSITY;
-        self.set_text_attributes(attrs);
-        self.bold = false;
+        self.state.reset();
+        self.update_text_attributes();
         Ok(())
     }
 
     fn add_style(&mut self, style: Style) -> io::Result<()> {
-        unsafe {
-            let mut attrs = match self.get_text_attributes() {
-                None => return Ok(()),
-                Some(attrs) => attrs,
-            };
-
-            match style {
-                Style::Bold => {
-                    self.bold = true;
-                    attrs |= FOREGROUND_INTENSITY
-                },
-                _ => {}
-            }
-
-            self.set_text_attributes(attrs);
+        self.state.add_style(style);
+        self.update_text_attributes();
+        Ok(())
+    }
 
-            Ok(())
-        }
+    fn remove_style(&mut self, style: Style) -> io::Result<()> {
+        self.state.remove_style(style);
+        self.update_text_attributes();
+        Ok(())
     }
 }
 
@@ -0,0 +1,235 @@
+//! Measures how fast `Shim` feeds typical output to terminals.
+//!
+//! Run with `cargo bench`. Each backend is measured twice: as is, receiving
+//! whole runs of text via `print_str`, and wrapped in `PerChar`, which only
+//! forwards `print` and so receives the text one char at a time.
+
+extern crate ansi_shim;
+
+use ansi_shim::{AnsiWriter, Color, GraphicState, PlainTextWriter, Screen, Shim, Style, Terminal};
+use std::io::{self, Write};
+use std::hint::black_box;
+use std::time::{Duration, Instant};
+
+/// The amount of input fed to each terminal
+const INPUT_SIZE: usize = 32 * 1024 * 1024;
+
+/// Hides `print_str` of the inner terminal, so it falls back to per-char `print`
+struct PerChar<T>(T);
+
+impl<T: Terminal> Terminal for PerChar<T> {
+    fn print(&mut self, ch: char) -> io::Result<()> {
+        self.0.print(ch)
+    }
+
+    fn set_fg_color(&mut self, color: Color) -> io::Result<()> {
+        self.0.set_fg_color(color)
+    }
+
+    fn set_bg_color(&mut self, color: Color) -> io::Result<()> {
+        self.0.set_bg_color(color)
+    }
+
+    fn reset_style(&mut self) -> io::Result<()> {
+        self.0.reset_style()
+    }
+
+    fn add_style(&mut self, style: Style) -> io::Result<()> {
+        self.0.add_style(style)
+    }
+
+    fn remove_style(&mut self, style: Style) -> io::Result<()> {
+        self.0.remove_style(style)
+    }
+
+    fn carriage_return(&mut self) -> io::Result<()> {
+        self.0.carriage_return()
+    }
+
+    fn line_feed(&mut self) -> io::Result<()> {
+        self.0.line_feed()
+    }
+}
+
+impl<T: Terminal> Write for PerChar<T> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.0.flush()
+    }
+}
+
+/// A cut down version of ansi2html's `HtmlWriter`, which lives in a binary and so
+/// cannot be used here: it escapes the text and reopens a styled span on the
+/// first print after an attribute change
+struct Html<W: Write> {
+    writer: W,
+    state: GraphicState,
+    span_needs_reopen: bool,
+}
+
+impl<W: Write> Html<W> {
+    fn new(writer: W) -> Self {
+        Html { writer, state: GraphicState::new(), span_needs_reopen: true }
+    }
+
+    fn reopen_span(&mut self) -> io::Result<()> {
+        if !self.span_needs_reopen {
+            return Ok(());
+        }
+
+        self.span_needs_reopen = false;
+        self.writer.write_all(b"</span><span style='")?;
+
+        if let Some((r, g, b)) = self.state.fg_color().rgb() {
+            write!(self.writer, "color: #{:02X}{:02X}{:02X};", r, g, b)?;
+        }
+
+        if let Some((r, g, b)) = self.state.bg_color().rgb() {
+            write!(self.writer, "background-color: #{:02X}{:02X}{:02X};", r, g, b)?;
+        }
+
+        if self.state.has_style(Style::Bold) {
+            self.writer.write_all(b"font-weight: bold;")?;
+        }
+
+        self.writer.write_all(b"'>")
+    }
+}
+
+fn escape_html_char(ch: char) -> Option<&'static str> {
+    match ch {
+        '&' => Some("&amp;"),
+        '<' => Some("&lt;"),
+        '>' => Some("&gt;"),
+        '"' => Some("&quot;"),
+        '\'' => Some("&#x27;"),
+        _ => None,
+    }
+}
+
+impl<W: Write> Terminal for Html<W> {
+    fn print(&mut self, ch: char) -> io::Result<()> {
+        self.reopen_span()?;
+        match escape_html_char(ch) {
+            Some(escaped) => self.writer.write_all(escaped.as_bytes()),
+            None => write!(self.writer, "{}", ch),
+        }
+    }
+
+    fn print_str(&mut self, text: &str) -> io::Result<()> {
+        self.reopen_span()?;
+        let mut escaped = String::with_capacity(text.len());
+
+        for ch in text.chars() {
+            match escape_html_char(ch) {
+                Some(entity) => escaped += entity,
+                None => escaped.push(ch),
+            }
+        }
+
+        self.writer.write_all(escaped.as_bytes())
+    }
+
+    fn set_fg_color(&mut self, color: Color) -> io::Result<()> {
+        self.span_needs_reopen = true;
+        self.state.set_fg_color(color);
+        Ok(())
+    }
+
+    fn set_bg_color(&mut self, color: Color) -> io::Result<()> {
+        self.span_needs_reopen = true;
+        self.state.set_bg_color(color);
+        Ok(())
+    }
+
+    fn reset_style(&mut self) -> io::Result<()> {
+        self.span_needs_reopen = true;
+        self.state.reset();
+        Ok(())
+    }
+
+    fn add_style(&mut self, style: Style) -> io::Result<()> {
+        self.span_needs_reopen = true;
+        self.state.add_style(style);
+        Ok(())
+    }
+
+    fn remove_style(&mut self, style: Style) -> io::Result<()> {
+        self.span_needs_reopen = true;
+        self.state.remove_style(style);
+        Ok(())
+    }
+}
+
+impl<W: Write> Write for Html<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.writer.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
+}
+
+/// Discards the output, but unlike `io::sink` the writes cannot be optimized away
+struct Discard;
+
+impl Write for Discard {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        Ok(black_box(buf).len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Mostly plain lines, as in build logs
+fn plain_input() -> Vec<u8> {
+    let line = "   Compiling ansi_shim v0.1.0 (/home/user/ansi_shim) – ünïcödé text\r\n";
+    line.bytes().cycle().take(INPUT_SIZE).collect()
+}
+
+/// Short colored words, as in `ls --color` or syntax highlighted output
+fn colored_input() -> Vec<u8> {
+    let line = "\x1b[1;34msrc\x1b[0m  \x1b[32mCargo.toml\x1b[0m  \x1b[38;5;208mtarget\x1b[0m  README.md\r\n";
+    line.bytes().cycle().take(INPUT_SIZE).collect()
+}
+
+fn run<T: Terminal>(terminal: T, input: &[u8]) -> Duration {
+    let mut shim = Shim::new(terminal);
+    let start = Instant::now();
+
+    // Pipes and ptys hand out data in chunks of this size
+    for chunk in input.chunks(64 * 1024) {
+        shim.write_all(chunk).unwrap();
+    }
+
+    start.elapsed()
+}
+
+fn report<F: FnMut(&[u8]) -> Duration>(name: &str, input: &[u8], mut bench: F) {
+    // The fastest of a few runs is the least disturbed one
+    let elapsed = (0..3).map(|_| bench(input)).min().unwrap();
+    let throughput = input.len() as f64 / elapsed.as_secs_f64() / 1_000_000.0;
+
+    println!("{:<32} {:>10.1} MB/s", name, throughput);
+}
+
+fn main() {
+    for &(kind, ref input) in &[("plain", plain_input()), ("colored", colored_input())] {
+        println!("{} input:", kind);
+
+        report("  AnsiWriter, per char", input, |input| run(PerChar(AnsiWriter::new(Discard)), input));
+        report("  AnsiWriter, print_str", input, |input| run(AnsiWriter::new(Discard), input));
+        report("  PlainTextWriter, per char", input, |input| run(PerChar(PlainTextWriter::new(Discard)), input));
+        report("  PlainTextWriter, print_str", input, |input| run(PlainTextWriter::new(Discard), input));
+        report("  Html, per char", input, |input| run(PerChar(Html::new(Discard)), input));
+        report("  Html, print_str", input, |input| run(Html::new(Discard), input));
+        report("  Screen, per char", input, |input| run(PerChar(Screen::new(80, 24)), input));
+        report("  Screen, print_str", input, |input| run(Screen::new(80, 24), input));
+    }
+}
@@ -0,0 +1,445 @@
+use core::fmt::{self, Write as FmtWrite};
+use io::{self, Write};
+use prelude::*;
+use {Color, EraseMode, GraphicState, Style, Terminal};
+
+/// A terminal that writes canonicalized ANSI escape sequences.
+///
+/// Attribute changes are only emitted once they affect output, merged into
+/// a single SGR sequence, using whichever is shorter of an incremental update
+/// or a reset followed by the full state.
+/// Use `finish` to reset the attributes at the end of the output.
+pub struct AnsiWriter<W: Write> {
+    writer: W,
+    /// The attributes the receiving terminal currently has
+    emitted: GraphicState,
+    /// The attributes the next output should have
+    pending: GraphicState,
+    /// Scratch buffers for the two candidate SGR params, kept to avoid allocating on every change
+    update: String,
+    reset: String,
+}
+
+impl<W: Write> AnsiWriter<W> {
+    pub fn new(writer: W) -> Self {
+        Self {
+            writer,
+            emitted: GraphicState::new(),
+            pending: GraphicState::new(),
+            update: String::new(),
+            reset: String::new(),
+        }
+    }
+
+    pub fn get_ref(&self) -> &W {
+        &self.writer
+    }
+
+    pub fn get_mut(&mut self) -> &mut W {
+        &mut self.writer
+    }
+
+    /// Resets the attributes if needed and returns the inner writer
+    pub fn finish(mut self) -> io::Result<W> {
+        self.pending = GraphicState::new();
+        self.sync_attributes()?;
+        self.writer.flush()?;
+        Ok(self.writer)
+    }
+
+    fn sync_attributes(&mut self) -> io::Result<()> {
+        if self.emitted == self.pending {
+            return Ok(());
+        }
+
+        self.update.clear();
+        sgr_update(&self.emitted, &self.pending, &mut self.update);
+        self.reset.clear();
+        self.reset.push('0');
+        sgr_update(&GraphicState::new(), &self.pending, &mut self.reset);
+
+        let params = if self.update.len() < self.reset.len() { &self.update } else { &self.reset };
+        write!(self.writer, "\x1b[{}m", params)?;
+        self.emitted.clone_from(&self.pending);
+
+        Ok(())
+    }
+
+    fn csi(&mut self, params: &str, cmd: char) -> io::Result<()> {
+        write!(self.writer, "\x1b[{}{}", params, cmd)
+    }
+}
+
+/// Omits the count if it is the default of 1
+fn count_param(n: usize) -> String {
+    if n == 1 { String::new() } else { n.to_string() }
+}
+
+/// Appends the SGR params that turn the `from` attributes into the `to` attributes
+/// to `params`, separated by semicolons
+fn sgr_update(from: &GraphicState, to: &GraphicState, params: &mut String) {
+    // The off codes are all below 128, so a bit set of them fits into a u128
+    let mut off = 0u128;
+
+    for &style in from.styles().difference(to.styles()) {
+        off |= 1 << style_off_code(style);
+    }
+
+    let mut remaining = off;
+    while remaining != 0 {
+        push_param(params, format_args!("{}", remaining.trailing_zeros()));
+        remaining &= remaining - 1;
+    }
+
+    // Off codes that cover multiple styles need the remaining ones re-enabled
+    for &style in to.styles() {
+        if !from.has_style(style) || off & 1 << style_off_code(style) != 0 {
+            push_param(params, format_args!("{}", style_on_code(style)));
+        }
+    }
+
+    if from.fg_color() != to.fg_color() {
+        color_params(to.fg_color(), false, params);
+    }
+
+    if from.bg_color() != to.bg_color() {
+        color_params(to.bg_color(), true, params);
+    }
+
+    if from.underline_color() != to.underline_color() {
+        underline_color_params(to.underline_color(), params);
+    }
+}
+
+fn push_param(params: &mut String, param: fmt::Arguments) {
+    if !params.is_empty() {
+        params.push(';');
+    }
+
+    // Writing to a `String` cannot fail
+    let _ = params.write_fmt(param);
+}
+
+/// The codes selecting the primary font and the alternative fonts 1 to 9
+const ALTERNATE_FONT_CODES: [&str; 10] = ["10", "11", "12", "13", "14", "15", "16", "17", "18", "19"];
+
+fn style_on_code(style: Style) -> &'static str {
+    match style {
+        Style::Bold => "1",
+        Style::Faint => "2",
+        Style::Italic => "3",
+        Style::Underline => "4",
+        Style::DoubleUnderline => "4:2",
+        Style::CurlyUnderline => "4:3",
+        Style::DottedUnderline => "4:4",
+        Style::DashedUnderline => "4:5",
+        Style::BlinkSlow => "5",
+        Style::BlinkFast => "6",
+        Style::Reverse => "7",
+        Style::Hidden => "8",
+        Style::Crossed => "9",
+        Style::AlternateFont(font) => ALTERNATE_FONT_CODES.get(usize::from(font)).cloned().unwrap_or("10"),
+        Style::Fraktur => "20",
+        Style::Framed => "51",
+        Style::Encircled => "52",
+        Style::Overline => "53",
+        Style::Superscript => "73",
+        Style::Subscript => "74",
+    }
+}
+
+fn style_off_code(style: Style) -> u8 {
+    match style {
+        Style::Bold | Style::Faint => 22,
+        Style::Italic | Style::Fraktur => 23,
+        Style::Underline
+        | Style::DoubleUnderline
+        | Style::CurlyUnderline
+        | Style::DottedUnderline
+        | Style::DashedUnderline => 24,
+        Style::BlinkSlow | Style::BlinkFast => 25,
+        Style::Reverse => 27,
+        Style::Hidden => 28,
+        Style::Crossed => 29,
+        Style::AlternateFont(_) => 10,
+        Style::Framed | Style::Encircled => 54,
+        Style::Overline => 55,
+        Style::Superscript | Style::Subscript => 75,
+    }
+}
+
+fn color_params(color: Color, background: bool, params: &mut String) {
+    use Color::*;
+    let offset = if background { 10 } else { 0 };
+
+    let code = match color {
+        Default => 39,
+        Black => 30,
+        Red => 31,
+        Green => 32,
+        Yellow => 33,
+        Blue => 34,
+        Magenta => 35,
+        Cyan => 36,
+        White => 37,
+        BrightBlack => 90,
+        BrightRed => 91,
+        BrightGreen => 92,
+        BrightYellow => 93,
+        BrightBlue => 94,
+        BrightMagenta => 95,
+        BrightCyan => 96,
+        BrightWhite => 97,
+        Indexed(index) => {
+            return push_param(params, format_args!("{};5;{}", 38 + offset, index));
+        }
+        Rgb(r, g, b) => {
+            return push_param(params, format_args!("{};2;{};{};{}", 38 + offset, r, g, b));
+        }
+    };
+
+    push_param(params, format_args!("{}", code + offset))
+}
+
+/// There are no short codes for underline colors, so basic colors use their palette index
+fn underline_color_params(color: Color, params: &mut String) {
+    match color {
+        Color::Default => push_param(params, format_args!("59")),
+        Color::Rgb(r, g, b) => push_param(params, format_args!("58;2;{};{};{}", r, g, b)),
+        Color::Indexed(index) => push_param(params, format_args!("58;5;{}", index)),
+        basic => {
+            let index = (0..16).find(|&index| Color::from_index(index) == basic).unwrap_or(0);
+            push_param(params, format_args!("58;5;{}", index))
+        }
+    }
+}
+
+impl<W: Write> Terminal for AnsiWriter<W> {
+    fn print(&mut self, ch: char) -> io::Result<()> {
+        self.sync_attributes()?;
+        write!(self.writer, "{}", ch)
+    }
+
+    fn print_str(&mut self, text: &str) -> io::Result<()> {
+        self.sync_attributes()?;
+        self.writer.write_all(text.as_bytes())
+    }
+
+    fn set_fg_color(&mut self, color: Color) -> io::Result<()> {
+        self.pending.set_fg_color(color);
+        Ok(())
+    }
+
+    fn set_bg_color(&mut self, color: Color) -> io::Result<()> {
+        self.pending.set_bg_color(color);
+        Ok(())
+    }
+
+    fn set_underline_color(&mut self, color: Color) -> io::Result<()> {
+        self.pending.set_underline_color(color);
+        Ok(())
+    }
+
+    fn reset_style(&mut self) -> io::Result<()> {
+        self.pending.reset();
+        Ok(())
+    }
+
+    fn add_style(&mut self, style: Style) -> io::Result<()> {
+        self.pending.add_style(style);
+        Ok(())
+    }
+
+    fn remove_style(&mut self, style: Style) -> io::Result<()> {
+        self.pending.remove_style(style);
+        Ok(())
+    }
+
+    fn cursor_up(&mut self, n: usize) -> io::Result<()> {
+        self.csi(&count_param(n), 'A')
+    }
+
+    fn cursor_down(&mut self, n: usize) -> io::Result<()> {
+        self.csi(&count_param(n), 'B')
+    }
+
+    fn cursor_forward(&mut self, n: usize) -> io::Result<()> {
+        self.csi(&count_param(n), 'C')
+    }
+
+    fn cursor_backward(&mut self, n: usize) -> io::Result<()> {
+        self.csi(&count_param(n), 'D')
+    }
+
+    fn set_cursor_position(&mut self, row: usize, column: usize) -> io::Result<()> {
+        match (row, column) {
+            (0, 0) => self.csi("", 'H'),
+            (row, 0) => self.csi(&(row + 1).to_string(), 'H'),
+            (row, column) => self.csi(&format!("{};{}", row + 1, column + 1), 'H'),
+        }
+    }
+
+    fn set_cursor_column(&mut self, column: usize) -> io::Result<()> {
+        self.csi(&count_param(column + 1), 'G')
+    }
+
+    fn set_cursor_row(&mut self, row: usize) -> io::Result<()> {
+        self.csi(&count_param(row + 1), 'd')
+    }
+
+    fn erase_in_display(&mut self, mode: EraseMode) -> io::Result<()> {
+        // The erased cells take the background color
+        self.sync_attributes()?;
+
+        match mode {
+            EraseMode::ToEnd => self.csi("", 'J'),
+            EraseMode::ToStart => self.csi("1", 'J'),
+            EraseMode::All => self.csi("2", 'J'),
+        }
+    }
+
+    fn erase_in_line(&mut self, mode: EraseMode) -> io::Result<()> {
+        // The erased cells take the background color
+        self.sync_attributes()?;
+
+        match mode {
+            EraseMode::ToEnd => self.csi("", 'K'),
+            EraseMode::ToStart => self.csi("1", 'K'),
+            EraseMode::All => self.csi("2", 'K'),
+        }
+    }
+
+    fn start_hyperlink(&mut self, uri: &str, id: Option<&str>) -> io::Result<()> {
+        match id {
+            Some(id) => write!(self.writer, "\x1b]8;id={};{}\x1b\\", id, uri),
+            None => write!(self.writer, "\x1b]8;;{}\x1b\\", uri),
+        }
+    }
+
+    fn end_hyperlink(&mut self) -> io::Result<()> {
+        write!(self.writer, "\x1b]8;;\x1b\\")
+    }
+
+    fn set_title(&mut self, title: &str) -> io::Result<()> {
+        write!(self.writer, "\x1b]2;{}\x1b\\", title)
+    }
+
+    fn set_icon_name(&mut self, name: &str) -> io::Result<()> {
+        write!(self.writer, "\x1b]1;{}\x1b\\", name)
+    }
+
+    fn carriage_return(&mut self) -> io::Result<()> {
+        self.write_all(b"\r")
+    }
+
+    fn line_feed(&mut self) -> io::Result<()> {
+        self.write_all(b"\n")
+    }
+
+    fn backspace(&mut self) -> io::Result<()> {
+        self.write_all(b"\x08")
+    }
+
+    fn tab(&mut self) -> io::Result<()> {
+        self.write_all(b"\t")
+    }
+
+    fn bell(&mut self) -> io::Result<()> {
+        self.write_all(b"\x07")
+    }
+
+    /// Writes C0 controls, C1 controls are dropped as they are
+    /// not valid UTF-8 on their own and would start 8-bit sequences
+    fn control(&mut self, byte: u8) -> io::Result<()> {
+        if byte < 0x20 {
+            self.write_all(&[byte])
+        } else {
+            Ok(())
+        }
+    }
+
+    fn device_control(&mut self, params: &[i64], intermediates: &[u8], action: char, data: &[u8]) -> io::Result<()> {
+        // Sixel images take the background color
+        self.sync_attributes()?;
+
+        let params = params.iter().map(|param| param.to_string()).collect::<Vec<_>>();
+        write!(self.writer, "\x1bP{}", params.join(";"))?;
+        self.writer.write_all(intermediates)?;
+        write!(self.writer, "{}", action)?;
+        self.writer.write_all(data)?;
+        self.writer.write_all(b"\x1b\\")
+    }
+}
+
+impl<W: Write> Write for AnsiWriter<W> {
+    /// Writes raw bytes, after applying pending attribute changes,
+    /// as e.g. line feeds that scroll fill with the background color
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.sync_attributes()?;
+        self.writer.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::AnsiWriter;
+    use io::Write;
+    use prelude::*;
+    use Shim;
+
+    fn rewrite(input: &str) -> String {
+        let mut shim = Shim::new(AnsiWriter::new(Vec::new()));
+        shim.write_all(input.as_bytes()).unwrap();
+        String::from_utf8(shim.into_inner().finish().unwrap()).unwrap()
+    }
+
+    #[test]
+    fn unused_attributes_are_dropped() {
+        assert_eq!(rewrite("a\x1b[1m\x1b[0mb"), "ab");
+        assert_eq!(rewrite("\x1b[31m\x1b[32mx"), "\x1b[32mx\x1b[0m");
+    }
+
+    #[test]
+    fn changes_are_merged() {
+        assert_eq!(rewrite("\x1b[1m\x1b[31m\x1b[4mx"), "\x1b[1;4;31mx\x1b[0m");
+        assert_eq!(rewrite("\x1b[58;2;1;2;3;4mx"), "\x1b[4;58;2;1;2;3mx\x1b[0m");
+    }
+
+    #[test]
+    fn incremental_update_when_shorter() {
+        assert_eq!(rewrite("\x1b[1;31;44mx\x1b[22my"), "\x1b[1;31;44mx\x1b[22my\x1b[0m");
+        assert_eq!(rewrite("\x1b[31mx\x1b[1my"), "\x1b[31mx\x1b[1my\x1b[0m");
+    }
+
+    #[test]
+    fn reset_when_shorter() {
+        assert_eq!(rewrite("\x1b[1;31mx\x1b[39;22;4my"), "\x1b[1;31mx\x1b[0;4my\x1b[0m");
+        assert_eq!(rewrite("\x1b[1;3mx\x1b[mx"), "\x1b[1;3mx\x1b[0mx");
+    }
+
+    #[test]
+    fn shared_off_codes_re_enable_the_rest() {
+        // 23 turns off both italic and Fraktur
+        assert_eq!(
+            rewrite("\x1b[3;20;38;2;1;2;3mx\x1b[23;20my"),
+            "\x1b[3;20;38;2;1;2;3mx\x1b[23;20my\x1b[0m",
+        );
+        // Faint replaces bold, both are turned off by 22
+        assert_eq!(rewrite("\x1b[1;38;5;100mx\x1b[2my"), "\x1b[1;38;5;100mx\x1b[22;2my\x1b[0m");
+    }
+
+    #[test]
+    fn fonts() {
+        assert_eq!(rewrite("\x1b[12;38;5;100mx\x1b[10my"), "\x1b[12;38;5;100mx\x1b[10my\x1b[0m");
+    }
+
+    #[test]
+    fn underline_colors_use_palette_indices() {
+        assert_eq!(rewrite("\x1b[4;58;5;1mx"), "\x1b[4;58;5;1mx\x1b[0m");
+        assert_eq!(rewrite("\x1b[4;58:5:200mx\x1b[59my"), "\x1b[4;58;5;200mx\x1b[59my\x1b[0m");
+    }
+}
@@ -0,0 +1,268 @@
+use std::io::{self, Write};
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use tokio::io::AsyncWrite;
+use {AnsiWriter, Downsample, PlainTextWriter, Shim, Terminal};
+
+/// The amount of buffered output above which `Shim` stops accepting input
+/// until the inner writer has caught up
+const HIGH_WATER_MARK: usize = 64 * 1024;
+
+/// An in-memory `Write` buffer in front of an `AsyncWrite`.
+///
+/// Terminals write to it without ever blocking,
+/// the output is forwarded to the async writer when polled.
+pub struct AsyncBuffer<W: AsyncWrite + Unpin> {
+    writer: W,
+    buf: Vec<u8>,
+    /// The number of bytes at the start of `buf` that have already been written
+    written: usize,
+}
+
+impl<W: AsyncWrite + Unpin> AsyncBuffer<W> {
+    pub fn new(writer: W) -> Self {
+        Self {
+            writer,
+            buf: Vec::new(),
+            written: 0,
+        }
+    }
+
+    pub fn get_ref(&self) -> &W {
+        &self.writer
+    }
+
+    pub fn get_mut(&mut self) -> &mut W {
+        &mut self.writer
+    }
+
+    /// Returns the inner writer, discarding any output that has not been written yet
+    pub fn into_inner(self) -> W {
+        self.writer
+    }
+
+    /// The number of bytes waiting to be written
+    pub fn buffered(&self) -> usize {
+        self.buf.len() - self.written
+    }
+
+    /// Writes as much buffered output as possible,
+    /// ready once all of it has been written
+    pub fn poll_drain(&mut self, cx: &mut Context) -> Poll<io::Result<()>> {
+        while self.written < self.buf.len() {
+            let n = match Pin::new(&mut self.writer).poll_write(cx, &self.buf[self.written..]) {
+                Poll::Ready(Ok(0)) => return Poll::Ready(Err(io::ErrorKind::WriteZero.into())),
+                Poll::Ready(Ok(n)) => n,
+                Poll::Ready(Err(error)) => return Poll::Ready(Err(error)),
+                Poll::Pending => {
+                    // Drop what has been written so far, so the buffer
+                    // does not keep growing while the writer lags behind
+                    if self.written > 0 {
+                        self.buf.drain(..self.written);
+                        self.written = 0;
+                    }
+
+                    return Poll::Pending;
+                },
+            };
+
+            self.written += n;
+        }
+
+        self.buf.clear();
+        self.written = 0;
+
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl<W: AsyncWrite + Unpin> Write for AsyncBuffer<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.buf.extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    /// Does nothing, as flushing has to wait for the async writer.
+    /// Use `AsyncWrite::poll_flush` instead.
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl<W: AsyncWrite + Unpin> AsyncWrite for AsyncBuffer<W> {
+    fn poll_write(self: Pin<&mut Self>, cx: &mut Context, buf: &[u8]) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+
+        match this.poll_drain(cx) {
+            Poll::Ready(Ok(())) => Pin::new(&mut this.writer).poll_write(cx, buf),
+            Poll::Ready(Err(error)) => Poll::Ready(Err(error)),
+            Poll::Pending => Poll::Pending,
+        }
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+
+        match this.poll_drain(cx) {
+            Poll::Ready(Ok(())) => Pin::new(&mut this.writer).poll_flush(cx),
+            other => other,
+        }
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+
+        match this.poll_drain(cx) {
+            Poll::Ready(Ok(())) => Pin::new(&mut this.writer).poll_shutdown(cx),
+            other => other,
+        }
+    }
+}
+
+/// A terminal that writes its output to an `AsyncBuffer`,
+/// which makes `Shim` an `AsyncWrite`
+pub trait AsyncTerminal: Terminal {
+    type Writer: AsyncWrite + Unpin;
+
+    fn buffer(&mut self) -> &mut AsyncBuffer<Self::Writer>;
+
+    /// Does the terminal's end of output work, like resetting the attributes,
+    /// and returns the buffer, which still has to be shut down
+    fn finish(self) -> io::Result<AsyncBuffer<Self::Writer>> where Self: Sized;
+}
+
+impl<W: AsyncWrite + Unpin> AsyncTerminal for AnsiWriter<AsyncBuffer<W>> {
+    type Writer = W;
+
+    fn buffer(&mut self) -> &mut AsyncBuffer<W> {
+        self.get_mut()
+    }
+
+    fn finish(self) -> io::Result<AsyncBuffer<W>> {
+        self.finish()
+    }
+}
+
+impl<W: AsyncWrite + Unpin> AsyncTerminal for PlainTextWriter<AsyncBuffer<W>> {
+    type Writer = W;
+
+    fn buffer(&mut self) -> &mut AsyncBuffer<W> {
+        self.get_mut()
+    }
+
+    fn finish(self) -> io::Result<AsyncBuffer<W>> {
+        self.finish()
+    }
+}
+
+impl<T: AsyncTerminal> AsyncTerminal for Downsample<T> {
+    type Writer = T::Writer;
+
+    fn buffer(&mut self) -> &mut AsyncBuffer<T::Writer> {
+        self.get_mut().buffer()
+    }
+
+    fn finish(self) -> io::Result<AsyncBuffer<T::Writer>> {
+        self.into_inner().finish()
+    }
+}
+
+impl<T: AsyncTerminal + Unpin> AsyncWrite for Shim<T> {
+    /// Feeds `buf` to the parser once the output of previous writes
+    /// has mostly been written, so a slow writer slows down the input.
+    ///
+    /// Parsing never blocks, the terminal only writes to its `AsyncBuffer`.
+    fn poll_write(self: Pin<&mut Self>, cx: &mut Context, buf: &[u8]) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+        let buffer = this.get_mut().buffer();
+
+        match buffer.poll_drain(cx) {
+            Poll::Ready(Err(error)) => return Poll::Ready(Err(error)),
+            Poll::Pending if buffer.buffered() >= HIGH_WATER_MARK => return Poll::Pending,
+            _ => {},
+        }
+
+        Poll::Ready(Write::write(this, buf))
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+
+        if let Err(error) = Write::flush(this) {
+            return Poll::Ready(Err(error));
+        }
+
+        Pin::new(this.get_mut().buffer()).poll_flush(cx)
+    }
+
+    /// Writes the remaining output and shuts down the inner writer.
+    ///
+    /// This does not finish the terminal, so e.g. the attributes are not reset.
+    /// To do that, call `AsyncTerminal::finish` on `into_inner` and shut down
+    /// the returned buffer instead.
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context) -> Poll<io::Result<()>> {
+        Pin::new(self.get_mut().get_mut().buffer()).poll_shutdown(cx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::task::Waker;
+
+    /// A writer that is pending on every other call and then takes at most 100 bytes
+    struct SlowWriter {
+        output: Vec<u8>,
+        ready: bool,
+    }
+
+    impl AsyncWrite for SlowWriter {
+        fn poll_write(self: Pin<&mut Self>, _cx: &mut Context, buf: &[u8]) -> Poll<io::Result<usize>> {
+            let this = self.get_mut();
+            this.ready = !this.ready;
+
+            if !this.ready {
+                return Poll::Pending;
+            }
+
+            let n = buf.len().min(100);
+            this.output.extend_from_slice(&buf[..n]);
+            Poll::Ready(Ok(n))
+        }
+
+        fn poll_flush(self: Pin<&mut Self>, _cx: &mut Context) -> Poll<io::Result<()>> {
+            Poll::Ready(Ok(()))
+        }
+
+        fn poll_shutdown(self: Pin<&mut Self>, _cx: &mut Context) -> Poll<io::Result<()>> {
+            Poll::Ready(Ok(()))
+        }
+    }
+
+    #[test]
+    fn backpressure() {
+        let mut cx = Context::from_waker(Waker::noop());
+        let writer = SlowWriter { output: Vec::new(), ready: false };
+        let mut shim = Shim::new(AnsiWriter::new(AsyncBuffer::new(writer)));
+        let input = "\x1b[31mred\x1b[0m plain\n".repeat(20_000);
+        let mut rest = input.as_bytes();
+        let mut pending = 0;
+
+        while !rest.is_empty() {
+            match Pin::new(&mut shim).poll_write(&mut cx, &rest[..rest.len().min(1000)]) {
+                Poll::Ready(Ok(n)) => rest = &rest[n..],
+                Poll::Ready(Err(error)) => panic!("{}", error),
+                Poll::Pending => pending += 1,
+            }
+
+            let buffer = shim.get_mut().buffer();
+            assert!(buffer.buf.len() < HIGH_WATER_MARK + 1000 * 2);
+        }
+
+        while Pin::new(&mut shim).poll_flush(&mut cx).is_pending() {}
+
+        assert!(pending > 0);
+        let output = shim.into_inner().finish().unwrap().into_inner().output;
+        assert_eq!(String::from_utf8(output).unwrap(), input);
+    }
+}
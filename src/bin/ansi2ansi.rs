@@ -0,0 +1,33 @@
+extern crate ansi_shim;
+#[cfg(feature = "env_logger")] extern crate env_logger;
+
+use ansi_shim::{AnsiWriter,ColorDepth,Downsample,Shim};
+use std::io::{self, BufWriter};
+use std::env::args;
+
+fn parse_depth(depth: &str) -> Option<ColorDepth> {
+    match depth {
+        "truecolor" => Some(ColorDepth::TrueColor),
+        "256" => Some(ColorDepth::Indexed256),
+        "16" => Some(ColorDepth::Basic16),
+        "8" => Some(ColorDepth::Basic8),
+        "mono" => Some(ColorDepth::Monochrome),
+        _ => None,
+    }
+}
+
+fn main() {
+    #[cfg(feature = "env_logger")]
+    env_logger::Builder::new().parse("ansi_shim=debug").init();
+
+    let depth = args().nth(1).unwrap_or_else(|| "truecolor".into());
+    let depth = parse_depth(&depth).expect("The color depth needs to be one of truecolor, 256, 16, 8 or mono");
+    let stdout = io::stdout();
+    let out = AnsiWriter::new(BufWriter::new(stdout.lock()));
+    let mut out = Shim::new(Downsample::new(out, depth));
+    let stdin = io::stdin();
+    let mut stdin = stdin.lock();
+
+    io::copy(&mut stdin, &mut out).unwrap();
+    out.into_inner().into_inner().finish().unwrap();
+}
@@ -1,28 +1,20 @@
 extern crate ansi_shim;
-extern crate env_logger;
+#[cfg(feature = "env_logger")] extern crate env_logger;
+#[macro_use] extern crate log;
 
-// TODO: Provide finalize method to check for errors when writing html footer
-
-use ansi_shim::{Terminal,Shim,Color,Style};
-use std::io::{self, Write};
+use ansi_shim::{Terminal,Shim,Color,Style,GraphicState,SixelImage};
+use std::io::{self, Write, BufWriter};
 use std::env::args;
 use std::fs::File;
 use std::collections::BTreeSet;
 
-enum Blink {
-    Off,
-    Slow,
-    Fast,
-}
-
 struct HtmlWriter<W: Write> {
     writer: W,
-    fg_color: Color,
-    bg_color: Color,
-    bold: bool,
-    blink: Blink,
-    styles: BTreeSet<Style>,
+    state: GraphicState,
     span_needs_reopen: bool,
+    in_hyperlink: bool,
+    /// The last title set by the input
+    title: Option<String>,
 }
 
 const HEADER: &str = "\
@@ -71,6 +63,23 @@ const HEADER: &str = "\
         .bg-bright-cyan { background-color: #18FFFF; }
         .bg-bright-white { background-color: #FFFFFF; }
 
+        .ul-black { text-decoration-color: #212121; }
+        .ul-red { text-decoration-color: #E51C23; }
+        .ul-green { text-decoration-color: #259B24; }
+        .ul-yellow { text-decoration-color: #FFEB3B; }
+        .ul-blue { text-decoration-color: #5677FC; }
+        .ul-magenta { text-decoration-color: #9C27B0; }
+        .ul-cyan { text-decoration-color: #00BCD4; }
+        .ul-white { text-decoration-color: #F5F5F5; }
+        .ul-bright-black { text-decoration-color: #9E9E9E; }
+        .ul-bright-red { text-decoration-color: #FF5177; }
+        .ul-bright-green { text-decoration-color: #5AF158; }
+        .ul-bright-yellow { text-decoration-color: #FFFF00; }
+        .ul-bright-blue { text-decoration-color: #6889FF; }
+        .ul-bright-magenta { text-decoration-color: #E040FB; }
+        .ul-bright-cyan { text-decoration-color: #18FFFF; }
+        .ul-bright-white { text-decoration-color: #FFFFFF; }
+
         .bold { font-weight: bold; }
 
         .blink { animation: blink 1.5s steps(2, start) infinite; }
@@ -88,12 +97,10 @@ impl<W: Write> HtmlWriter<W> {
     pub fn new(writer: W) -> io::Result<Self> {
         let mut writer = HtmlWriter {
             writer,
-            fg_color: Color::White,
-            bg_color: Color::Black,
-            bold: false,
-            blink: Blink::Off,
-            styles: BTreeSet::new(),
+            state: GraphicState::new(),
             span_needs_reopen: false,
+            in_hyperlink: false,
+            title: None,
         };
         writer.write_all(HEADER.as_bytes())?;
         writer.open_span()?;
@@ -102,21 +109,45 @@ impl<W: Write> HtmlWriter<W> {
     }
 
     fn open_span(&mut self) -> io::Result<()> {
-        let fg = self.fg_color_class();
-        let bg = self.bg_color_class();
-        write!(self.writer, "<span class='{fg} {bg}{bold}{blink}' style='{style}'>",
+        let state = &self.state;
+        let fg = color_class(state.fg_color()).unwrap_or("");
+        let bg = color_class(state.bg_color()).map(|class| format!("bg-{}", class)).unwrap_or_default();
+        let underline = color_class(state.underline_color()).map(|class| format!(" ul-{}", class)).unwrap_or_default();
+        write!(self.writer, "<span class='{fg} {bg}{underline}{bold}{blink}' style='{colors}{style}'>",
             fg = fg,
             bg = bg,
-            bold = if self.bold { " bold" } else { "" },
-            blink = match self.blink {
-                Blink::Off => "",
-                Blink::Slow => " blink",
-                Blink::Fast => " blink-fast",
+            underline = underline,
+            bold = if state.has_style(Style::Bold) { " bold" } else { "" },
+            blink = if state.has_style(Style::BlinkSlow) {
+                " blink"
+            } else if state.has_style(Style::BlinkFast) {
+                " blink-fast"
+            } else {
+                ""
             },
-            style = ansi_style_to_html(&self.styles),
+            colors = ansi_colors_to_html(state.fg_color(), state.bg_color(), state.underline_color()),
+            style = ansi_style_to_html(state.styles()),
         )
     }
 
+    /// Writes the footer, flushes and returns the inner writer
+    pub fn finish(mut self) -> io::Result<W> {
+        self.end_hyperlink()?;
+        self.close_span()?;
+        self.writer.write_all("\n</pre>\n".as_bytes())?;
+
+        // The title is only known once all input has been processed,
+        // by then the head with the default title has long been written
+        if let Some(ref title) = self.title {
+            write!(self.writer, "\n<script>document.title = \"{}\";</script>\n", escape_js_string(title))?;
+        }
+
+        self.writer.write_all("\n</body>\n</html>".as_bytes())?;
+        self.writer.flush()?;
+
+        Ok(self.writer)
+    }
+
     fn close_span(&mut self) -> io::Result<()> {
         self.writer.write_all("</span>".as_bytes())
     }
@@ -134,50 +165,6 @@ impl<W: Write> HtmlWriter<W> {
 
         Ok(())
     }
-
-    fn fg_color_class(&self) -> &'static str {
-        use self::Color::*;
-        match self.fg_color {
-            Black => "black",
-            Red => "red",
-            Green => "green",
-            Yellow => "yellow",
-            Blue => "blue",
-            Magenta => "magenta",
-            Cyan => "cyan",
-            White => "white",
-            BrightBlack => "bright-black",
-            BrightRed => "bright-red",
-            BrightGreen => "bright-green",
-            BrightYellow => "bright-yellow",
-            BrightBlue => "bright-blue",
-            BrightMagenta => "bright-magenta",
-            BrightCyan => "bright-cyan",
-            BrightWhite => "bright-white",
-        }
-    }
-
-    fn bg_color_class(&self) -> &'static str {
-        use self::Color::*;
-        match self.bg_color {
-            Black => "bg-black",
-            Red => "bg-red",
-            Green => "bg-green",
-            Yellow => "bg-yellow",
-            Blue => "bg-blue",
-            Magenta => "bg-magenta",
-            Cyan => "bg-cyan",
-            White => "bg-white",
-            BrightBlack => "bg-bright-black",
-            BrightRed => "bg-bright-red",
-            BrightGreen => "bg-bright-green",
-            BrightYellow => "bg-bright-yellow",
-            BrightBlue => "bg-bright-blue",
-            BrightMagenta => "bg-bright-magenta",
-            BrightCyan => "bg-bright-cyan",
-            BrightWhite => "bg-bright-white",
-        }
-    }
 }
 
 impl<W: Write> Write for HtmlWriter<W> {
@@ -194,91 +181,305 @@ impl<W: Write> Write for HtmlWriter<W> {
 impl<W: Write> Terminal for HtmlWriter<W> {
     fn print(&mut self, ch: char) -> io::Result<()> {
         self.execute_reopen_span()?;
-        match ch {
-            '&' => write!(self.writer, "&amp;"),
-            '<' => write!(self.writer, "&lt;"),
-            '>' => write!(self.writer, "&gt;"),
-            '"' => write!(self.writer, "&#x27;"),
-            '\'' => write!(self.writer, "&#x2F;"),
-            _ => write!(self.writer, "{}", ch),
+        match escape_html_char(ch) {
+            Some(escaped) => write!(self.writer, "{}", escaped),
+            None => write!(self.writer, "{}", ch),
         }
     }
 
+    fn print_str(&mut self, text: &str) -> io::Result<()> {
+        self.execute_reopen_span()?;
+        self.writer.write_all(escape_html(text).as_bytes())
+    }
+
     fn set_fg_color(&mut self, color: Color) -> io::Result<()> {
         self.reopen_span();
-        self.fg_color = color;
+        self.state.set_fg_color(color);
         Ok(())
     }
 
     fn set_bg_color(&mut self, color: Color) -> io::Result<()> {
         self.reopen_span();
-        self.bg_color = color;
+        self.state.set_bg_color(color);
+        Ok(())
+    }
+
+    fn set_underline_color(&mut self, color: Color) -> io::Result<()> {
+        self.reopen_span();
+        self.state.set_underline_color(color);
         Ok(())
     }
 
     fn reset_style(&mut self) -> io::Result<()> {
         self.reopen_span();
-        self.styles.clear();
-        self.fg_color = Color::White;
-        self.bg_color = Color::Black;
-        self.bold = false;
-        self.blink = Blink::Off;
+        self.state.reset();
         Ok(())
     }
 
     fn add_style(&mut self, style: Style) -> io::Result<()> {
         self.reopen_span();
+        self.state.add_style(style);
+        Ok(())
+    }
 
-        match style {
-            Style::BlinkSlow => self.blink = Blink::Slow,
-            Style::BlinkFast => self.blink = Blink::Fast,
-            Style::Bold => self.bold = true,
-            Style::Faint => self.bold = false,
-            _ => { self.styles.insert(style); },
-        };
+    fn remove_style(&mut self, style: Style) -> io::Result<()> {
+        self.reopen_span();
+        self.state.remove_style(style);
+        Ok(())
+    }
+
+    fn start_hyperlink(&mut self, uri: &str, _id: Option<&str>) -> io::Result<()> {
+        self.end_hyperlink()?;
+
+        if !is_safe_uri(uri) {
+            debug!("Not linking unsafe URI: {:?}", uri);
+            return Ok(());
+        }
+
+        self.close_span()?;
+        write!(self.writer, "<a href='{}'>", escape_html(uri))?;
+        self.in_hyperlink = true;
+        self.open_span()?;
+        self.span_needs_reopen = false;
 
         Ok(())
     }
+
+    fn end_hyperlink(&mut self) -> io::Result<()> {
+        if !self.in_hyperlink {
+            return Ok(());
+        }
+
+        self.close_span()?;
+        self.writer.write_all("</a>".as_bytes())?;
+        self.in_hyperlink = false;
+        self.open_span()?;
+        self.span_needs_reopen = false;
+
+        Ok(())
+    }
+
+    fn set_title(&mut self, title: &str) -> io::Result<()> {
+        self.title = Some(title.into());
+        Ok(())
+    }
+
+    fn device_control(&mut self, params: &[i64], _intermediates: &[u8], action: char, data: &[u8]) -> io::Result<()> {
+        if action != 'q' {
+            debug!("Unhandled DCS: {:?} {:?}", params, action);
+            return Ok(());
+        }
+
+        let image = SixelImage::decode(params, data);
+
+        if image.width == 0 || image.height == 0 {
+            return Ok(());
+        }
+
+        self.execute_reopen_span()?;
+        write!(
+            self.writer,
+            "<img width='{}' height='{}' src='data:image/png;base64,{}'>",
+            image.width,
+            image.height,
+            base64(&image.to_png()),
+        )
+    }
+}
+
+fn base64(data: &[u8]) -> String {
+    const ALPHABET: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    let mut encoded = String::with_capacity(data.len().div_ceil(3) * 4);
+
+    for chunk in data.chunks(3) {
+        let bytes = [chunk[0], *chunk.get(1).unwrap_or(&0), *chunk.get(2).unwrap_or(&0)];
+        let group = u32::from(bytes[0]) << 16 | u32::from(bytes[1]) << 8 | u32::from(bytes[2]);
+
+        for i in 0..4 {
+            if i <= chunk.len() {
+                encoded.push(ALPHABET[(group >> (18 - 6 * i) & 0x3F) as usize] as char);
+            } else {
+                encoded.push('=');
+            }
+        }
+    }
+
+    encoded
+}
+
+fn escape_html_char(ch: char) -> Option<&'static str> {
+    match ch {
+        '&' => Some("&amp;"),
+        '<' => Some("&lt;"),
+        '>' => Some("&gt;"),
+        '"' => Some("&quot;"),
+        '\'' => Some("&#x27;"),
+        _ => None,
+    }
+}
+
+fn escape_html(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+
+    for ch in text.chars() {
+        match escape_html_char(ch) {
+            Some(entity) => escaped += entity,
+            None => escaped.push(ch),
+        }
+    }
+
+    escaped
+}
+
+/// Escapes `text` for a double quoted JavaScript string inside a `<script>` element
+fn escape_js_string(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+
+    for ch in text.chars() {
+        match ch {
+            '\\' => escaped += "\\\\",
+            '"' => escaped += "\\\"",
+            // Also keeps `</script>` from ending the element
+            '<' | '>' | '&' | '\u{2028}' | '\u{2029}' => escaped += &format!("\\u{:04x}", ch as u32),
+            ch if ch.is_control() => escaped += &format!("\\u{:04x}", ch as u32),
+            ch => escaped.push(ch),
+        }
+    }
+
+    escaped
+}
+
+/// Only link schemes that cannot run code in the browser
+fn is_safe_uri(uri: &str) -> bool {
+    const SAFE_SCHEMES: &[&str] = &["http:", "https:", "ftp:", "file:", "mailto:"];
+    let uri = uri.trim_start().to_lowercase();
+
+    SAFE_SCHEMES.iter().any(|scheme| uri.starts_with(scheme))
+}
+
+fn color_class(color: Color) -> Option<&'static str> {
+    use self::Color::*;
+    Some(match color {
+        Black => "black",
+        Red => "red",
+        Green => "green",
+        Yellow => "yellow",
+        Blue => "blue",
+        Magenta => "magenta",
+        Cyan => "cyan",
+        White => "white",
+        BrightBlack => "bright-black",
+        BrightRed => "bright-red",
+        BrightGreen => "bright-green",
+        BrightYellow => "bright-yellow",
+        BrightBlue => "bright-blue",
+        BrightMagenta => "bright-magenta",
+        BrightCyan => "bright-cyan",
+        BrightWhite => "bright-white",
+        Default | Indexed(_) | Rgb(..) => return None,
+    })
+}
+
+fn ansi_colors_to_html(fg: Color, bg: Color, underline: Color) -> String {
+    let mut css = String::new();
+
+    if color_class(fg).is_none() {
+        if let Some((r, g, b)) = fg.rgb() {
+            css += &format!("color: #{:02X}{:02X}{:02X};", r, g, b);
+        }
+    }
+
+    if color_class(bg).is_none() {
+        if let Some((r, g, b)) = bg.rgb() {
+            css += &format!("background-color: #{:02X}{:02X}{:02X};", r, g, b);
+        }
+    }
+
+    if color_class(underline).is_none() {
+        if let Some((r, g, b)) = underline.rgb() {
+            css += &format!("text-decoration-color: #{:02X}{:02X}{:02X};", r, g, b);
+        }
+    }
+
+    css
 }
 
 fn ansi_style_to_html(styles: &BTreeSet<Style>) -> String {
     let mut css = String::new();
     let mut deco = String::new();
+    let mut deco_style = None;
 
     for &style in styles {
         match style {
             Style::Italic => css += "font-style: italic;",
             Style::Hidden => css += "visibility: hidden;",
             Style::Underline => deco += " underline",
+            Style::DoubleUnderline => {
+                deco += " underline";
+                deco_style = Some("double");
+            },
+            Style::CurlyUnderline => {
+                deco += " underline";
+                deco_style = Some("wavy");
+            },
+            Style::DottedUnderline => {
+                deco += " underline";
+                deco_style = Some("dotted");
+            },
+            Style::DashedUnderline => {
+                deco += " underline";
+                deco_style = Some("dashed");
+            },
             Style::Crossed => deco += " line-through",
+            Style::Overline => deco += " overline",
+            Style::AlternateFont(font) => css += &format!("font-family: {};", font_family(font)),
+            Style::Fraktur => css += "font-family: UnifrakturMaguntia, fantasy;",
+            Style::Framed => css += "outline: 1px solid;",
+            Style::Encircled => css += "outline: 1px solid; border-radius: 0.5em;",
+            Style::Superscript => css += "vertical-align: super; font-size: smaller;",
+            Style::Subscript => css += "vertical-align: sub; font-size: smaller;",
             _ => {},
         };
     }
 
     if !deco.is_empty() {
-        use std::fmt::Write;
-        write!(css, "text-decoration: {};", deco);
+        css += &format!("text-decoration-line: {};", deco);
+    }
+
+    if let Some(deco_style) = deco_style {
+        css += &format!("text-decoration-style: {};", deco_style);
     }
 
     css
 }
 
-impl<W: Write> Drop for HtmlWriter<W> {
-    fn drop(&mut self) {
-        self.close_span().and_then(|_| {
-            self.writer.write_all("\n</pre>\n\n</body>\n</html>".as_bytes())
-        }).expect("writing html footer");
+/// Terminals leave the alternative fonts up to configuration,
+/// so they map to the generic CSS font families
+fn font_family(font: u8) -> &'static str {
+    match font {
+        1 => "serif",
+        2 => "sans-serif",
+        3 => "cursive",
+        4 => "fantasy",
+        5 => "system-ui",
+        6 => "ui-serif",
+        7 => "ui-sans-serif",
+        8 => "ui-rounded",
+        _ => "math",
     }
 }
 
+
 fn main() {
+    #[cfg(feature = "env_logger")]
     env_logger::Builder::new().parse("ansi_shim=debug").init();
     let path = args().nth(1).expect("The first argument needs to be the output file");
     let out = File::create(path).expect("output file");
-    let out = HtmlWriter::new(out).expect("writing html init");
+    let out = HtmlWriter::new(BufWriter::new(out)).expect("writing html init");
     let mut out = Shim::new(out);
     let stdin = io::stdin();
     let mut stdin = stdin.lock();
 
     io::copy(&mut stdin, &mut out).unwrap();
+    out.into_inner().finish().unwrap();
 }
@@ -0,0 +1,32 @@
+extern crate ansi_shim;
+#[cfg(feature = "env_logger")] extern crate env_logger;
+
+use ansi_shim::{Allowed,Sanitizer,Shim};
+use std::io::{self, BufWriter};
+use std::env::args;
+
+fn parse_allowed(allowed: &str) -> Option<Allowed> {
+    match allowed {
+        "text" => Some(Allowed::TEXT),
+        "sgr" => Some(Allowed::SGR),
+        // Also allows erasing, which is needed to redraw
+        "cursor" => Some(Allowed::SGR_AND_CURSOR),
+        _ => None,
+    }
+}
+
+fn main() {
+    #[cfg(feature = "env_logger")]
+    env_logger::Builder::new().parse("ansi_shim=debug").init();
+
+    let allowed = args().nth(1).unwrap_or_else(|| "sgr".into());
+    let allowed = parse_allowed(&allowed).expect("The allowed sequences need to be one of text, sgr or cursor (which includes erasing)");
+    let stdout = io::stdout();
+    let out = Sanitizer::new(BufWriter::new(stdout.lock()), allowed);
+    let mut out = Shim::new(out);
+    let stdin = io::stdin();
+    let mut stdin = stdin.lock();
+
+    io::copy(&mut stdin, &mut out).unwrap();
+    out.into_inner().finish().unwrap();
+}
@@ -0,0 +1,35 @@
+extern crate ansi_shim;
+#[cfg(feature = "env_logger")] extern crate env_logger;
+
+use ansi_shim::{PlainTextWriter,Shim};
+use std::io::{self, Write, BufWriter};
+use std::env::args;
+use std::fs::File;
+
+fn main() {
+    #[cfg(feature = "env_logger")]
+    env_logger::Builder::new().parse("ansi_shim=debug").init();
+
+    let mut overwrite = false;
+    let mut path = None;
+
+    for arg in args().skip(1) {
+        match arg.as_str() {
+            "--overwrite" => overwrite = true,
+            _ => path = Some(arg),
+        }
+    }
+
+    let out: Box<dyn Write> = match path {
+        Some(path) => Box::new(File::create(path).expect("output file")),
+        None => Box::new(io::stdout()),
+    };
+    let mut out = PlainTextWriter::new(BufWriter::new(out));
+    out.set_overwrite(overwrite);
+    let mut out = Shim::new(out);
+    let stdin = io::stdin();
+    let mut stdin = stdin.lock();
+
+    io::copy(&mut stdin, &mut out).unwrap();
+    out.into_inner().finish().unwrap();
+}
@@ -0,0 +1,246 @@
+use io::{self, Write};
+use {Color, EraseMode, Style, Terminal};
+
+/// The colors a terminal is able to display
+#[derive(Copy,Clone,Debug,PartialEq,Eq,Hash)]
+pub enum ColorDepth {
+    /// 24-bit RGB colors
+    TrueColor,
+    /// The 256 color palette
+    Indexed256,
+    /// The 8 basic colors and their bright variants
+    Basic16,
+    /// The 8 basic colors
+    Basic8,
+    /// Only the default colors
+    Monochrome,
+}
+
+impl ColorDepth {
+    /// Returns the perceptually closest color available at this depth
+    pub fn quantize(self, color: Color) -> Color {
+        match (self, color) {
+            (_, Color::Default) => color,
+            (ColorDepth::TrueColor, _) => color,
+            (ColorDepth::Indexed256, Color::Rgb(..)) => {
+                nearest(color, (16..=255).map(Color::Indexed))
+            },
+            (ColorDepth::Indexed256, _) => color,
+            (ColorDepth::Basic16, _) => color.to_basic(),
+            (ColorDepth::Basic8, _) => color.to_basic().normal(),
+            (ColorDepth::Monochrome, _) => Color::Default,
+        }
+    }
+}
+
+/// Returns the candidate with the smallest perceptual distance to `color`,
+/// or `color` itself if it has no RGB value.
+pub(crate) fn nearest<I: Iterator<Item=Color>>(color: Color, candidates: I) -> Color {
+    let target = match color.rgb() {
+        Some(rgb) => oklab(rgb),
+        None => return color,
+    };
+
+    candidates
+        .filter_map(|candidate| candidate.rgb().map(|rgb| (candidate, oklab(rgb))))
+        .map(|(candidate, lab)| (candidate, distance(target, lab)))
+        .min_by(|&(_, a), &(_, b)| a.partial_cmp(&b).unwrap_or(::std::cmp::Ordering::Equal))
+        .map(|(candidate, _)| candidate)
+        .unwrap_or(color)
+}
+
+fn distance((l1, a1, b1): (f64, f64, f64), (l2, a2, b2): (f64, f64, f64)) -> f64 {
+    (l1 - l2).powi(2) + (a1 - a2).powi(2) + (b1 - b2).powi(2)
+}
+
+fn srgb_to_linear(channel: u8) -> f64 {
+    let channel = f64::from(channel) / 255.0;
+
+    if channel <= 0.04045 {
+        channel / 12.92
+    } else {
+        ((channel + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+/// Converts an sRGB color into the perceptually uniform Oklab color space
+fn oklab((r, g, b): (u8, u8, u8)) -> (f64, f64, f64) {
+    let (r, g, b) = (srgb_to_linear(r), srgb_to_linear(g), srgb_to_linear(b));
+
+    let l = (0.412_221_470_8 * r + 0.536_332_536_3 * g + 0.051_445_992_9 * b).cbrt();
+    let m = (0.211_903_498_2 * r + 0.680_699_545_1 * g + 0.107_396_956_6 * b).cbrt();
+    let s = (0.088_302_461_9 * r + 0.281_718_837_6 * g + 0.629_978_700_5 * b).cbrt();
+
+    (
+        0.210_454_255_3 * l + 0.793_617_785_0 * m - 0.004_072_046_8 * s,
+        1.977_998_495_1 * l - 2.428_592_205_0 * m + 0.450_593_709_9 * s,
+        0.025_904_037_1 * l + 0.782_771_766_2 * m - 0.808_675_766_0 * s,
+    )
+}
+
+/// A terminal adapter that reduces colors to what the inner terminal supports
+pub struct Downsample<T: Terminal> {
+    terminal: T,
+    depth: ColorDepth,
+}
+
+impl<T: Terminal> Downsample<T> {
+    pub fn new(terminal: T, depth: ColorDepth) -> Self {
+        Self {
+            terminal,
+            depth,
+        }
+    }
+
+    pub fn get_ref(&self) -> &T {
+        &self.terminal
+    }
+
+    pub fn get_mut(&mut self) -> &mut T {
+        &mut self.terminal
+    }
+
+    pub fn into_inner(self) -> T {
+        self.terminal
+    }
+}
+
+impl<T: Terminal> Terminal for Downsample<T> {
+    fn print(&mut self, ch: char) -> io::Result<()> {
+        self.terminal.print(ch)
+    }
+
+    fn print_str(&mut self, text: &str) -> io::Result<()> {
+        self.terminal.print_str(text)
+    }
+
+    fn set_fg_color(&mut self, color: Color) -> io::Result<()> {
+        self.terminal.set_fg_color(self.depth.quantize(color))
+    }
+
+    fn set_bg_color(&mut self, color: Color) -> io::Result<()> {
+        self.terminal.set_bg_color(self.depth.quantize(color))
+    }
+
+    fn set_underline_color(&mut self, color: Color) -> io::Result<()> {
+        self.terminal.set_underline_color(self.depth.quantize(color))
+    }
+
+    fn reset_style(&mut self) -> io::Result<()> {
+        self.terminal.reset_style()
+    }
+
+    fn add_style(&mut self, style: Style) -> io::Result<()> {
+        self.terminal.add_style(style)
+    }
+
+    fn remove_style(&mut self, style: Style) -> io::Result<()> {
+        self.terminal.remove_style(style)
+    }
+
+    fn reset_fg_color(&mut self) -> io::Result<()> {
+        self.terminal.reset_fg_color()
+    }
+
+    fn reset_bg_color(&mut self) -> io::Result<()> {
+        self.terminal.reset_bg_color()
+    }
+
+    fn cursor_up(&mut self, n: usize) -> io::Result<()> {
+        self.terminal.cursor_up(n)
+    }
+
+    fn cursor_down(&mut self, n: usize) -> io::Result<()> {
+        self.terminal.cursor_down(n)
+    }
+
+    fn cursor_forward(&mut self, n: usize) -> io::Result<()> {
+        self.terminal.cursor_forward(n)
+    }
+
+    fn cursor_backward(&mut self, n: usize) -> io::Result<()> {
+        self.terminal.cursor_backward(n)
+    }
+
+    fn set_cursor_position(&mut self, row: usize, column: usize) -> io::Result<()> {
+        self.terminal.set_cursor_position(row, column)
+    }
+
+    fn set_cursor_column(&mut self, column: usize) -> io::Result<()> {
+        self.terminal.set_cursor_column(column)
+    }
+
+    fn set_cursor_row(&mut self, row: usize) -> io::Result<()> {
+        self.terminal.set_cursor_row(row)
+    }
+
+    fn erase_in_display(&mut self, mode: EraseMode) -> io::Result<()> {
+        self.terminal.erase_in_display(mode)
+    }
+
+    fn erase_in_line(&mut self, mode: EraseMode) -> io::Result<()> {
+        self.terminal.erase_in_line(mode)
+    }
+
+    fn start_hyperlink(&mut self, uri: &str, id: Option<&str>) -> io::Result<()> {
+        self.terminal.start_hyperlink(uri, id)
+    }
+
+    fn end_hyperlink(&mut self) -> io::Result<()> {
+        self.terminal.end_hyperlink()
+    }
+
+    fn set_title(&mut self, title: &str) -> io::Result<()> {
+        self.terminal.set_title(title)
+    }
+
+    fn set_icon_name(&mut self, name: &str) -> io::Result<()> {
+        self.terminal.set_icon_name(name)
+    }
+
+    fn carriage_return(&mut self) -> io::Result<()> {
+        self.terminal.carriage_return()
+    }
+
+    fn line_feed(&mut self) -> io::Result<()> {
+        self.terminal.line_feed()
+    }
+
+    fn backspace(&mut self) -> io::Result<()> {
+        self.terminal.backspace()
+    }
+
+    fn tab(&mut self) -> io::Result<()> {
+        self.terminal.tab()
+    }
+
+    fn bell(&mut self) -> io::Result<()> {
+        self.terminal.bell()
+    }
+
+    fn control(&mut self, byte: u8) -> io::Result<()> {
+        self.terminal.control(byte)
+    }
+
+    fn device_control(&mut self, params: &[i64], intermediates: &[u8], action: char, data: &[u8]) -> io::Result<()> {
+        self.terminal.device_control(params, intermediates, action, data)
+    }
+
+    fn query_cursor_position(&self) -> Option<(usize, usize)> {
+        self.terminal.query_cursor_position()
+    }
+
+    fn query_size(&self) -> Option<(usize, usize)> {
+        self.terminal.query_size()
+    }
+}
+
+impl<T: Terminal> Write for Downsample<T> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.terminal.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.terminal.flush()
+    }
+}
@@ -0,0 +1,346 @@
+use alloc::collections::VecDeque;
+use core::mem;
+#[cfg(feature = "std")]
+use std::io::{self, Read};
+use prelude::*;
+use vte::{Params, Parser, Perform};
+use {flat_params, param_groups, DCS_LIMIT};
+use sgr::{self, SgrAttr};
+
+#[cfg(feature = "std")]
+const READ_BUFFER_SIZE: usize = 8 * 1024;
+
+/// A parsed piece of terminal output
+#[derive(Clone,Debug,PartialEq,Eq)]
+pub enum Event {
+    /// A run of printable text.
+    /// It is owned rather than borrowed from the input, as the parser
+    /// hands text over char by char and `ReadEvents` reuses its buffer.
+    Text(String),
+    /// A C0 or C1 control character
+    Control(u8),
+    /// A graphic rendition sequence (`CSI ... m`)
+    Sgr(Vec<SgrAttr>),
+    /// Any other control sequence
+    Csi {
+        params: Vec<i64>,
+        intermediates: Vec<u8>,
+        ignore: bool,
+        action: char,
+    },
+    /// An operating system command
+    Osc {
+        params: Vec<Vec<u8>>,
+    },
+    /// A device control string
+    Dcs {
+        params: Vec<i64>,
+        intermediates: Vec<u8>,
+        ignore: bool,
+        action: char,
+        data: Vec<u8>,
+    },
+    /// An escape sequence
+    Esc {
+        intermediates: Vec<u8>,
+        ignore: bool,
+        byte: u8,
+    },
+}
+
+/// Turns parser callbacks into events, coalescing printed characters
+#[derive(Default)]
+struct Collector {
+    text: String,
+    events: VecDeque<Event>,
+    /// The device control string being received
+    dcs: Option<Event>,
+}
+
+impl Collector {
+    fn push(&mut self, event: Event) {
+        self.flush_text();
+        self.events.push_back(event);
+    }
+
+    fn flush_text(&mut self) {
+        if !self.text.is_empty() {
+            let text = mem::take(&mut self.text);
+            self.events.push_back(Event::Text(text));
+        }
+    }
+}
+
+impl Perform for Collector {
+    fn print(&mut self, ch: char) {
+        self.text.push(ch);
+    }
+
+    fn execute(&mut self, byte: u8) {
+        self.push(Event::Control(byte));
+    }
+
+    fn hook(&mut self, params: &Params, intermediates: &[u8], ignore: bool, action: char) {
+        let params = match flat_params(params) {
+            Some(params) => params,
+            None => {
+                debug!("Unhandled subparams: {:?} {:?}", params, action);
+                return;
+            }
+        };
+
+        self.dcs = Some(Event::Dcs {
+            params,
+            intermediates: intermediates.to_vec(),
+            ignore,
+            action,
+            data: Vec::new(),
+        });
+    }
+
+    fn put(&mut self, byte: u8) {
+        let overflow = match self.dcs {
+            Some(Event::Dcs { ref mut data, .. }) if data.len() < DCS_LIMIT => {
+                data.push(byte);
+                false
+            },
+            Some(_) => true,
+            None => false,
+        };
+
+        if overflow {
+            debug!("Dropping DCS larger than {} bytes", DCS_LIMIT);
+            self.dcs = None;
+        }
+    }
+
+    fn unhook(&mut self) {
+        if let Some(event) = self.dcs.take() {
+            self.push(event);
+        }
+    }
+
+    fn osc_dispatch(&mut self, params: &[&[u8]], _bell_terminated: bool) {
+        self.push(Event::Osc {
+            params: params.iter().map(|param| param.to_vec()).collect(),
+        });
+    }
+
+    fn csi_dispatch(&mut self, params: &Params, intermediates: &[u8], ignore: bool, action: char) {
+        if action == 'm' && intermediates.is_empty() {
+            self.push(Event::Sgr(sgr::parse(&param_groups(params))));
+            return;
+        }
+
+        let params = match flat_params(params) {
+            Some(params) => params,
+            None => {
+                debug!("Unhandled subparams: {:?} {:?}", params, action);
+                return;
+            }
+        };
+
+        self.push(Event::Csi {
+            params,
+            intermediates: intermediates.to_vec(),
+            ignore,
+            action,
+        });
+    }
+
+    fn esc_dispatch(&mut self, intermediates: &[u8], ignore: bool, byte: u8) {
+        // String terminator of an OSC or DCS, which has already been dispatched
+        if byte == b'\\' && intermediates.is_empty() {
+            return;
+        }
+
+        self.push(Event::Esc {
+            intermediates: intermediates.to_vec(),
+            ignore,
+            byte,
+        });
+    }
+
+    /// Stops the parser once an event other than text is complete
+    fn terminated(&self) -> bool {
+        !self.events.is_empty()
+    }
+}
+
+/// An iterator over the events parsed from a byte slice
+pub struct Events<'a> {
+    input: &'a [u8],
+    parser: Parser,
+    collector: Collector,
+}
+
+impl<'a> Events<'a> {
+    pub fn new(input: &'a [u8]) -> Self {
+        Self {
+            input,
+            parser: Parser::new(),
+            collector: Collector::default(),
+        }
+    }
+}
+
+impl<'a> Iterator for Events<'a> {
+    type Item = Event;
+
+    fn next(&mut self) -> Option<Event> {
+        loop {
+            if let Some(event) = self.collector.events.pop_front() {
+                return Some(event);
+            }
+
+            if self.input.is_empty() {
+                self.collector.flush_text();
+                return self.collector.events.pop_front();
+            }
+
+            let len = self.parser.advance_until_terminated(&mut self.collector, self.input);
+            self.input = &self.input[len..];
+        }
+    }
+}
+
+/// An iterator over the events parsed from a reader.
+///
+/// Text runs are coalesced up to the end of each read from the reader.
+/// Iteration ends after the first read error.
+#[cfg(feature = "std")]
+pub struct ReadEvents<R: Read> {
+    reader: R,
+    buf: Vec<u8>,
+    done: bool,
+    parser: Parser,
+    collector: Collector,
+}
+
+#[cfg(feature = "std")]
+impl<R: Read> ReadEvents<R> {
+    pub fn new(reader: R) -> Self {
+        Self {
+            reader,
+            buf: vec![0; READ_BUFFER_SIZE],
+            done: false,
+            parser: Parser::new(),
+            collector: Collector::default(),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl<R: Read> Iterator for ReadEvents<R> {
+    type Item = io::Result<Event>;
+
+    fn next(&mut self) -> Option<io::Result<Event>> {
+        loop {
+            if let Some(event) = self.collector.events.pop_front() {
+                return Some(Ok(event));
+            }
+
+            if self.done {
+                return None;
+            }
+
+            match self.reader.read(&mut self.buf) {
+                Ok(0) => self.done = true,
+                Ok(len) => {
+                    self.parser.advance(&mut self.collector, &self.buf[..len]);
+
+                    self.collector.flush_text();
+                },
+                Err(ref error) if error.kind() == io::ErrorKind::Interrupted => {},
+                Err(error) => {
+                    self.done = true;
+                    return Some(Err(error));
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Event::{self, *};
+    use super::Events;
+    use prelude::*;
+    use sgr::SgrAttr;
+    use Color;
+
+    fn parse(input: &[u8]) -> Vec<Event> {
+        Events::new(input).collect()
+    }
+
+    fn text(text: &str) -> Event {
+        Text(text.into())
+    }
+
+    #[test]
+    fn text_is_coalesced() {
+        assert_eq!(parse("héllo wörld".as_bytes()), [text("héllo wörld")]);
+        assert_eq!(
+            parse(b"ab\x1b[31mcd"),
+            [text("ab"), Sgr(vec![SgrAttr::Foreground(Color::Red)]), text("cd")],
+        );
+        assert_eq!(parse(b"ab\ncd"), [text("ab"), Control(b'\n'), text("cd")]);
+    }
+
+    #[test]
+    fn csi_and_esc() {
+        let csi = Csi { params: vec![2, 3], intermediates: Vec::new(), ignore: false, action: 'H' };
+        let private = Csi { params: vec![1049], intermediates: b"?".to_vec(), ignore: false, action: 'h' };
+        let esc = Esc { intermediates: Vec::new(), ignore: false, byte: b'c' };
+
+        assert_eq!(parse(b"\x1b[2;3H\x1b[?1049h\x1bc"), [csi, private, esc]);
+    }
+
+    #[test]
+    fn osc_with_bel_and_st() {
+        let osc = || Osc { params: vec![b"0".to_vec(), b"title".to_vec()] };
+
+        assert_eq!(parse(b"a\x1b]0;title\x07b"), [text("a"), osc(), text("b")]);
+        assert_eq!(parse(b"a\x1b]0;title\x1b\\b"), [text("a"), osc(), text("b")]);
+    }
+
+    #[test]
+    fn dcs() {
+        let dcs = Dcs {
+            params: vec![0, 1],
+            intermediates: Vec::new(),
+            ignore: false,
+            action: 'q',
+            data: b"#0~-".to_vec(),
+        };
+
+        assert_eq!(parse(b"a\x1bP0;1q#0~-\x1b\\b"), [text("a"), dcs, text("b")]);
+    }
+
+    #[test]
+    #[cfg(feature = "std")]
+    fn read_events_across_reads() {
+        use std::io::{self, Read};
+        use super::ReadEvents;
+        use Style;
+
+        /// Returns the input a few bytes at a time
+        struct Chunks<'a>(&'a [u8]);
+
+        impl<'a> Read for Chunks<'a> {
+            fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+                let len = self.0.len().min(buf.len()).min(3);
+                buf[..len].copy_from_slice(&self.0[..len]);
+                self.0 = &self.0[len..];
+                Ok(len)
+            }
+        }
+
+        let events = ReadEvents::new(Chunks("ab\x1b]2;tïtle\x07é\x1b[1m".as_bytes()))
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+        let osc = Osc { params: vec![b"2".to_vec(), "tïtle".as_bytes().to_vec()] };
+
+        assert_eq!(events, [text("ab"), osc, text("é"), Sgr(vec![SgrAttr::AddStyle(Style::Bold)])]);
+    }
+}
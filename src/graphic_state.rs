@@ -0,0 +1,200 @@
+use alloc::collections::BTreeSet;
+use prelude::*;
+use {Color, SgrAttr, Style, ALTERNATE_FONTS, UNDERLINE_STYLES};
+
+/// Styles of which at most one can be active at the same time
+const EXCLUSIVE_STYLES: &[&[Style]] = &[
+    &[Style::Bold, Style::Faint],
+    &[Style::BlinkSlow, Style::BlinkFast],
+    UNDERLINE_STYLES,
+    ALTERNATE_FONTS,
+    &[Style::Framed, Style::Encircled],
+    &[Style::Superscript, Style::Subscript],
+];
+
+/// The graphic rendition attributes of a terminal, as set by SGR sequences
+#[derive(Clone,Debug,PartialEq,Eq,Hash)]
+pub struct GraphicState {
+    fg_color: Color,
+    bg_color: Color,
+    underline_color: Color,
+    styles: BTreeSet<Style>,
+}
+
+impl Default for GraphicState {
+    fn default() -> Self {
+        Self {
+            fg_color: Color::Default,
+            bg_color: Color::Default,
+            underline_color: Color::Default,
+            styles: BTreeSet::new(),
+        }
+    }
+}
+
+impl GraphicState {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn fg_color(&self) -> Color {
+        self.fg_color
+    }
+
+    pub fn bg_color(&self) -> Color {
+        self.bg_color
+    }
+
+    /// The color of underlines, `Color::Default` means the foreground color
+    pub fn underline_color(&self) -> Color {
+        self.underline_color
+    }
+
+    pub fn styles(&self) -> &BTreeSet<Style> {
+        &self.styles
+    }
+
+    pub fn has_style(&self, style: Style) -> bool {
+        self.styles.contains(&style)
+    }
+
+    /// Returns whether all attributes are at their defaults
+    pub fn is_default(&self) -> bool {
+        *self == GraphicState::default()
+    }
+
+    pub fn set_fg_color(&mut self, color: Color) {
+        self.fg_color = color;
+    }
+
+    pub fn set_bg_color(&mut self, color: Color) {
+        self.bg_color = color;
+    }
+
+    pub fn set_underline_color(&mut self, color: Color) {
+        self.underline_color = color;
+    }
+
+    /// Adds `style`, replacing any style that is mutually exclusive with it
+    pub fn add_style(&mut self, style: Style) {
+        for group in EXCLUSIVE_STYLES.iter().filter(|group| group.contains(&style)) {
+            for other in group.iter() {
+                self.styles.remove(other);
+            }
+        }
+
+        self.styles.insert(style);
+    }
+
+    pub fn remove_style(&mut self, style: Style) {
+        self.styles.remove(&style);
+    }
+
+    pub fn reset(&mut self) {
+        *self = GraphicState::default();
+    }
+
+    pub fn apply(&mut self, attr: SgrAttr) {
+        match attr {
+            SgrAttr::Reset => self.reset(),
+            SgrAttr::AddStyle(style) => self.add_style(style),
+            SgrAttr::RemoveStyle(style) => self.remove_style(style),
+            SgrAttr::Foreground(color) => self.set_fg_color(color),
+            SgrAttr::Background(color) => self.set_bg_color(color),
+            SgrAttr::UnderlineColor(color) => self.set_underline_color(color),
+        }
+    }
+
+    /// Returns the changes that turn this state into `target`
+    pub fn diff(&self, target: &GraphicState) -> Vec<SgrAttr> {
+        if self == target {
+            return Vec::new();
+        }
+
+        if target.is_default() {
+            return vec![SgrAttr::Reset];
+        }
+
+        let mut attrs = Vec::new();
+
+        attrs.extend(self.styles.difference(&target.styles).map(|&style| SgrAttr::RemoveStyle(style)));
+        attrs.extend(target.styles.difference(&self.styles).map(|&style| SgrAttr::AddStyle(style)));
+
+        if self.fg_color != target.fg_color {
+            attrs.push(SgrAttr::Foreground(target.fg_color));
+        }
+
+        if self.bg_color != target.bg_color {
+            attrs.push(SgrAttr::Background(target.bg_color));
+        }
+
+        if self.underline_color != target.underline_color {
+            attrs.push(SgrAttr::UnderlineColor(target.underline_color));
+        }
+
+        attrs
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::GraphicState;
+    use prelude::*;
+    use Color::*;
+    use SgrAttr::*;
+    use Style::{self, *};
+
+    fn with_styles(styles: &[Style]) -> GraphicState {
+        let mut state = GraphicState::new();
+        styles.iter().for_each(|&style| state.add_style(style));
+        state
+    }
+
+    fn styles(state: &GraphicState) -> Vec<Style> {
+        state.styles().iter().cloned().collect()
+    }
+
+    #[test]
+    fn exclusive_styles_replace_each_other() {
+        assert_eq!(styles(&with_styles(&[Bold, Faint])), [Faint]);
+        assert_eq!(styles(&with_styles(&[BlinkFast, BlinkSlow])), [BlinkSlow]);
+        assert_eq!(styles(&with_styles(&[Underline, CurlyUnderline])), [CurlyUnderline]);
+        assert_eq!(styles(&with_styles(&[AlternateFont(1), AlternateFont(2)])), [AlternateFont(2)]);
+        assert_eq!(styles(&with_styles(&[Framed, Encircled])), [Encircled]);
+        assert_eq!(styles(&with_styles(&[Superscript, Subscript])), [Subscript]);
+    }
+
+    #[test]
+    fn other_styles_combine() {
+        assert_eq!(styles(&with_styles(&[Bold, Italic, Fraktur, Underline])), [Bold, Italic, Underline, Fraktur]);
+    }
+
+    #[test]
+    fn diff() {
+        let plain = GraphicState::new();
+        let mut red_bold = with_styles(&[Bold]);
+        red_bold.set_fg_color(Red);
+        let mut faint = with_styles(&[Faint]);
+        faint.set_bg_color(Blue);
+
+        assert!(red_bold.diff(&red_bold).is_empty());
+        assert_eq!(red_bold.diff(&plain), [Reset]);
+        assert_eq!(plain.diff(&red_bold), [AddStyle(Bold), Foreground(Red)]);
+        assert_eq!(
+            red_bold.diff(&faint),
+            [RemoveStyle(Bold), AddStyle(Faint), Foreground(Default), Background(Blue)],
+        );
+    }
+
+    #[test]
+    fn applying_a_diff_reaches_the_target() {
+        let mut from = with_styles(&[Bold, Italic, AlternateFont(3)]);
+        from.set_underline_color(Indexed(100));
+        let mut to = with_styles(&[Faint, Italic, DoubleUnderline]);
+        to.set_fg_color(Rgb(1, 2, 3));
+
+        let mut state = from.clone();
+        from.diff(&to).into_iter().for_each(|attr| state.apply(attr));
+        assert_eq!(state, to);
+    }
+}
@@ -0,0 +1,659 @@
+use alloc::collections::VecDeque;
+use core::mem;
+#[cfg(feature = "std")]
+use std::io::{self, Read};
+use prelude::*;
+use vte::{Params, Parser, Perform};
+use flat_params;
+
+#[cfg(feature = "std")]
+const READ_BUFFER_SIZE: usize = 1024;
+
+/// A key on the keyboard
+#[derive(Copy,Clone,Debug,PartialEq,Eq,Hash)]
+pub enum Key {
+    /// A printable character, or a letter pressed with Ctrl
+    Char(char),
+    Enter,
+    Tab,
+    Backspace,
+    Escape,
+    Up,
+    Down,
+    Left,
+    Right,
+    Home,
+    End,
+    Insert,
+    Delete,
+    PageUp,
+    PageDown,
+    /// A function key, starting at 1
+    F(u8),
+}
+
+/// The modifier keys held down during a key press or mouse event
+#[derive(Copy,Clone,Debug,Default,PartialEq,Eq,Hash)]
+pub struct Modifiers {
+    pub shift: bool,
+    pub alt: bool,
+    pub ctrl: bool,
+    pub meta: bool,
+}
+
+impl Modifiers {
+    /// Decodes the modifier param of xterm style key sequences, e.g. the `5` of `CSI 1 ; 5 A`
+    fn from_param(param: i64) -> Self {
+        let bits = (param - 1).max(0);
+
+        Self {
+            shift: bits & 1 != 0,
+            alt: bits & 2 != 0,
+            ctrl: bits & 4 != 0,
+            meta: bits & 8 != 0,
+        }
+    }
+
+    fn ctrl() -> Self {
+        Self { ctrl: true, ..Self::default() }
+    }
+}
+
+#[derive(Copy,Clone,Debug,PartialEq,Eq,Hash)]
+pub enum MouseButton {
+    Left,
+    Middle,
+    Right,
+    WheelUp,
+    WheelDown,
+    WheelLeft,
+    WheelRight,
+    /// The extra buttons 8 to 11
+    Other(u8),
+}
+
+#[derive(Copy,Clone,Debug,PartialEq,Eq,Hash)]
+pub enum MouseAction {
+    Press,
+    Release,
+    /// Movement, with `button` held down if it is set
+    Move,
+}
+
+/// A mouse report
+#[derive(Copy,Clone,Debug,PartialEq,Eq,Hash)]
+pub struct MouseEvent {
+    pub action: MouseAction,
+    /// The button, not known for releases in the X10 encoding
+    pub button: Option<MouseButton>,
+    /// The zero-based row
+    pub row: usize,
+    /// The zero-based column
+    pub column: usize,
+    /// Shift, Alt and Ctrl, as far as the terminal reports them
+    pub modifiers: Modifiers,
+}
+
+impl MouseEvent {
+    /// Decodes the button byte and one-based coordinates shared by all mouse encodings
+    fn decode(code: i64, column: i64, row: i64, release: bool) -> Self {
+        let button = match (code & 0b1100_0000, code & 0b11) {
+            (0, 0) => Some(MouseButton::Left),
+            (0, 1) => Some(MouseButton::Middle),
+            (0, 2) => Some(MouseButton::Right),
+            (0, _) => None,
+            (64, 0) => Some(MouseButton::WheelUp),
+            (64, 1) => Some(MouseButton::WheelDown),
+            (64, 2) => Some(MouseButton::WheelLeft),
+            (64, _) => Some(MouseButton::WheelRight),
+            (_, n) => Some(MouseButton::Other(8 + n as u8)),
+        };
+        let action = match (release, code & 32 != 0) {
+            (true, _) => MouseAction::Release,
+            (false, true) => MouseAction::Move,
+            (false, false) if button.is_none() => MouseAction::Release,
+            (false, false) => MouseAction::Press,
+        };
+
+        Self {
+            action,
+            button,
+            row: (row - 1).max(0) as usize,
+            column: (column - 1).max(0) as usize,
+            modifiers: Modifiers {
+                shift: code & 4 != 0,
+                alt: code & 8 != 0,
+                ctrl: code & 16 != 0,
+                meta: false,
+            },
+        }
+    }
+}
+
+/// A piece of input sent by a terminal
+#[derive(Clone,Debug,PartialEq,Eq)]
+pub enum InputEvent {
+    Key(Key, Modifiers),
+    Mouse(MouseEvent),
+    /// The start of a bracketed paste (`CSI 200 ~`)
+    PasteStart,
+    /// Pasted text, possibly split into several events
+    Paste(String),
+    /// The end of a bracketed paste (`CSI 201 ~`)
+    PasteEnd,
+    FocusGained,
+    FocusLost,
+    /// A cursor position report (`CSI row ; column R`), zero-based.
+    /// F3 with modifiers is sent the same way, and is reported as this.
+    CursorPosition { row: usize, column: usize },
+}
+
+/// Turns parser callbacks into input events
+#[derive(Default)]
+struct Collector {
+    events: VecDeque<InputEvent>,
+    /// Set after an ESC prefix, the next key is pressed with Alt
+    alt: bool,
+    /// Set after `ESC O`, the next character selects the key
+    ss3: bool,
+    /// The bytes of an X10 mouse report received so far, after `CSI M`
+    x10: Option<Vec<u8>>,
+    /// The text pasted so far, while a bracketed paste is in progress
+    paste: Option<String>,
+}
+
+impl Collector {
+    fn push(&mut self, mut event: InputEvent) {
+        match event {
+            InputEvent::Key(_, ref mut modifiers) => modifiers.alt |= mem::take(&mut self.alt),
+            _ => self.alt = false,
+        }
+
+        self.events.push_back(event);
+    }
+
+    fn flush_paste(&mut self) {
+        if let Some(ref mut paste) = self.paste {
+            if !paste.is_empty() {
+                let text = mem::take(paste);
+                self.events.push_back(InputEvent::Paste(text));
+            }
+        }
+    }
+
+    /// Handles `ESC O` that turned out not to be followed by a key, which is Alt+O
+    fn flush_ss3(&mut self) {
+        if mem::take(&mut self.ss3) {
+            self.alt = true;
+            self.push(InputEvent::Key(Key::Char('O'), Modifiers::default()));
+        }
+    }
+
+    fn ss3_key(&mut self, ch: char) {
+        let key = match ch {
+            'A' => Key::Up,
+            'B' => Key::Down,
+            'C' => Key::Right,
+            'D' => Key::Left,
+            'H' => Key::Home,
+            'F' => Key::End,
+            'M' => Key::Enter,
+            'P' => Key::F(1),
+            'Q' => Key::F(2),
+            'R' => Key::F(3),
+            'S' => Key::F(4),
+            _ => {
+                debug!("Unhandled SS3 key: {:?}", ch);
+                return;
+            }
+        };
+
+        self.push(InputEvent::Key(key, Modifiers::default()));
+    }
+
+    /// Handles a complete X10 mouse report, each byte offset by 32
+    fn x10_mouse(&mut self, report: &[u8]) {
+        let value = |byte: u8| i64::from(byte) - 32;
+        let event = MouseEvent::decode(value(report[0]), value(report[1]), value(report[2]), false);
+        self.push(InputEvent::Mouse(event));
+    }
+
+    fn csi_event(&mut self, params: &[i64], intermediates: &[u8], action: char) -> Option<InputEvent> {
+        let modifiers = Modifiers::from_param(params.get(1).cloned().unwrap_or(1));
+        let key = |key| Some(InputEvent::Key(key, modifiers));
+
+        match (action, intermediates) {
+            ('A', []) => key(Key::Up),
+            ('B', []) => key(Key::Down),
+            ('C', []) => key(Key::Right),
+            ('D', []) => key(Key::Left),
+            ('H', []) => key(Key::Home),
+            ('F', []) => key(Key::End),
+            ('P', []) => key(Key::F(1)),
+            ('Q', []) => key(Key::F(2)),
+            ('S', []) => key(Key::F(4)),
+            ('Z', []) => Some(InputEvent::Key(Key::Tab, Modifiers { shift: true, ..modifiers })),
+            ('R', []) if params.len() == 2 => Some(InputEvent::CursorPosition {
+                row: (params[0] - 1).max(0) as usize,
+                column: (params[1] - 1).max(0) as usize,
+            }),
+            ('I', []) => Some(InputEvent::FocusGained),
+            ('O', []) => Some(InputEvent::FocusLost),
+            ('~', []) => match params.first().cloned().unwrap_or(0) {
+                200 => {
+                    self.paste = Some(String::new());
+                    Some(InputEvent::PasteStart)
+                },
+                // xterm's modifyOtherKeys: `CSI 27 ; modifiers ; code ~`
+                27 => code_key(params.get(2).cloned().unwrap_or(0)).and_then(key),
+                code => tilde_key(code).and_then(key),
+            },
+            // fixterms and kitty: `CSI code ; modifiers u`
+            ('u', []) => code_key(params.first().cloned().unwrap_or(0)).and_then(key),
+            // X10: `CSI M` followed by three bytes
+            ('M', []) if params.len() <= 1 => {
+                self.x10 = Some(Vec::with_capacity(3));
+                None
+            },
+            // urxvt: `CSI button ; column ; row M`, with the button offset by 32
+            ('M', []) if params.len() == 3 => {
+                Some(InputEvent::Mouse(MouseEvent::decode(params[0] - 32, params[1], params[2], false)))
+            },
+            // SGR: `CSI < button ; column ; row M`, or `m` on release
+            ('M', [b'<']) | ('m', [b'<']) if params.len() == 3 => {
+                Some(InputEvent::Mouse(MouseEvent::decode(params[0], params[1], params[2], action == 'm')))
+            },
+            _ => {
+                debug!("Unhandled input CSI: {:?} {:?} {:?}", params, intermediates, action);
+                None
+            }
+        }
+    }
+}
+
+/// The keys of `CSI code ~`
+fn tilde_key(code: i64) -> Option<Key> {
+    let key = match code {
+        1 | 7 => Key::Home,
+        2 => Key::Insert,
+        3 => Key::Delete,
+        4 | 8 => Key::End,
+        5 => Key::PageUp,
+        6 => Key::PageDown,
+        11..=15 => Key::F((code - 10) as u8),
+        17..=21 => Key::F((code - 11) as u8),
+        23..=26 => Key::F((code - 12) as u8),
+        28 | 29 => Key::F((code - 13) as u8),
+        31..=34 => Key::F((code - 14) as u8),
+        _ => {
+            debug!("Unhandled key code: {}", code);
+            return None;
+        }
+    };
+
+    Some(key)
+}
+
+/// The key of a Unicode code point, as sent by `CSI u` and modifyOtherKeys
+fn code_key(code: i64) -> Option<Key> {
+    match code {
+        9 => Some(Key::Tab),
+        13 => Some(Key::Enter),
+        27 => Some(Key::Escape),
+        127 => Some(Key::Backspace),
+        _ => char::from_u32(code as u32).map(Key::Char),
+    }
+}
+
+impl Perform for Collector {
+    fn print(&mut self, ch: char) {
+        if let Some(ref mut paste) = self.paste {
+            paste.push(ch);
+            return;
+        }
+
+        if mem::take(&mut self.ss3) {
+            self.ss3_key(ch);
+        } else if ch == '\x7f' {
+            self.push(InputEvent::Key(Key::Backspace, Modifiers::default()));
+        } else {
+            self.push(InputEvent::Key(Key::Char(ch), Modifiers::default()));
+        }
+    }
+
+    fn execute(&mut self, byte: u8) {
+        if let Some(ref mut paste) = self.paste {
+            paste.push(char::from(byte));
+            return;
+        }
+
+        self.ss3 = false;
+
+        let (key, modifiers) = match byte {
+            b'\r' | b'\n' => (Key::Enter, Modifiers::default()),
+            b'\t' => (Key::Tab, Modifiers::default()),
+            0x08 => (Key::Backspace, Modifiers::default()),
+            0x00 => (Key::Char(' '), Modifiers::ctrl()),
+            0x01..=0x1a => (Key::Char(char::from(b'a' + byte - 1)), Modifiers::ctrl()),
+            0x1c..=0x1f => (Key::Char(char::from(byte + 0x40)), Modifiers::ctrl()),
+            _ => {
+                debug!("Unhandled input control: {:#x}", byte);
+                return;
+            }
+        };
+
+        self.push(InputEvent::Key(key, modifiers));
+    }
+
+    fn csi_dispatch(&mut self, params: &Params, intermediates: &[u8], _ignore: bool, action: char) {
+        let params = match flat_params(params) {
+            Some(params) => params,
+            None => {
+                debug!("Unhandled subparams: {:?} {:?}", params, action);
+                return;
+            }
+        };
+
+        // Anything but the end of the paste is pasted text, but terminals drop escape sequences from it
+        if self.paste.is_some() {
+            if action == '~' && params == [201] {
+                self.flush_paste();
+                self.paste = None;
+                self.push(InputEvent::PasteEnd);
+            }
+
+            return;
+        }
+
+        if let Some(event) = self.csi_event(&params, intermediates, action) {
+            self.push(event);
+        }
+    }
+}
+
+/// Decodes input byte by byte.
+///
+/// The parser only sees control sequences and text,
+/// ESC prefixes are handled here, as `ESC P` or `ESC ]` are keys pressed with Alt,
+/// not the start of a string that swallows all following input.
+#[derive(Default)]
+struct Decoder {
+    parser: Parser,
+    collector: Collector,
+    /// Set after an ESC that has not been passed to the parser yet
+    escape: bool,
+    /// Set after `ESC [` until the next byte, as it might have been Alt+[
+    csi_started: bool,
+}
+
+impl Decoder {
+    fn advance(&mut self, byte: u8) {
+        let collector = &mut self.collector;
+        self.csi_started = false;
+
+        if let Some(mut report) = collector.x10.take() {
+            report.push(byte);
+
+            if report.len() == 3 {
+                collector.x10_mouse(&report);
+            } else {
+                collector.x10 = Some(report);
+            }
+
+            return;
+        }
+
+        // The paste ends with an escape sequence, which must reach the parser
+        if collector.paste.is_some() {
+            self.parser.advance(collector, &[byte]);
+            return;
+        }
+
+        if self.escape {
+            self.escape = false;
+
+            match byte {
+                b'[' => {
+                    self.parser.advance(collector, b"\x1b[");
+                    self.csi_started = true;
+                    return;
+                },
+                b'O' => {
+                    collector.ss3 = true;
+                    return;
+                },
+                // Alt+Escape, or Alt followed by an escape sequence
+                0x1b => {
+                    collector.alt = true;
+                    self.escape = true;
+                    return;
+                },
+                _ => collector.alt = true,
+            }
+        } else if byte == 0x1b {
+            // Like the parser itself, ESC aborts any unfinished sequence
+            self.parser = Parser::new();
+            collector.flush_ss3();
+            self.escape = true;
+            return;
+        }
+
+        self.parser.advance(collector, &[byte]);
+    }
+
+    /// Resolves what is ambiguous until the next byte arrives, at the end of each read.
+    /// Terminals send escape sequences in one piece, so what is left was typed.
+    fn flush(&mut self) {
+        let collector = &mut self.collector;
+
+        if mem::take(&mut self.escape) {
+            collector.push(InputEvent::Key(Key::Escape, Modifiers::default()));
+        }
+
+        if mem::take(&mut self.csi_started) {
+            self.parser = Parser::new();
+            collector.alt = true;
+            collector.push(InputEvent::Key(Key::Char('['), Modifiers::default()));
+        }
+
+        collector.flush_ss3();
+        collector.flush_paste();
+    }
+}
+
+/// An iterator over the input events parsed from a byte slice.
+///
+/// An ESC at the end of the slice is the Escape key.
+pub struct Input<'a> {
+    input: &'a [u8],
+    decoder: Decoder,
+}
+
+impl<'a> Input<'a> {
+    pub fn new(input: &'a [u8]) -> Self {
+        Self {
+            input,
+            decoder: Decoder::default(),
+        }
+    }
+}
+
+impl<'a> Iterator for Input<'a> {
+    type Item = InputEvent;
+
+    fn next(&mut self) -> Option<InputEvent> {
+        loop {
+            if let Some(event) = self.decoder.collector.events.pop_front() {
+                return Some(event);
+            }
+
+            match self.input.split_first() {
+                Some((&byte, rest)) => {
+                    self.input = rest;
+                    self.decoder.advance(byte);
+                },
+                None => {
+                    self.decoder.flush();
+                    return self.decoder.collector.events.pop_front();
+                }
+            }
+        }
+    }
+}
+
+/// An iterator over the input events parsed from a reader, usually stdin in raw mode.
+///
+/// An ESC at the end of a read is the Escape key, as terminals send
+/// escape sequences in one write. Iteration ends after the first read error.
+#[cfg(feature = "std")]
+pub struct ReadInput<R: Read> {
+    reader: R,
+    buf: Vec<u8>,
+    done: bool,
+    decoder: Decoder,
+}
+
+#[cfg(feature = "std")]
+impl<R: Read> ReadInput<R> {
+    pub fn new(reader: R) -> Self {
+        Self {
+            reader,
+            buf: vec![0; READ_BUFFER_SIZE],
+            done: false,
+            decoder: Decoder::default(),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl<R: Read> Iterator for ReadInput<R> {
+    type Item = io::Result<InputEvent>;
+
+    fn next(&mut self) -> Option<io::Result<InputEvent>> {
+        loop {
+            if let Some(event) = self.decoder.collector.events.pop_front() {
+                return Some(Ok(event));
+            }
+
+            if self.done {
+                return None;
+            }
+
+            match self.reader.read(&mut self.buf) {
+                Ok(0) => {
+                    self.done = true;
+                    self.decoder.flush();
+                },
+                Ok(len) => {
+                    for &byte in &self.buf[..len] {
+                        self.decoder.advance(byte);
+                    }
+
+                    self.decoder.flush();
+                },
+                Err(ref error) if error.kind() == io::ErrorKind::Interrupted => {},
+                Err(error) => {
+                    self.done = true;
+                    return Some(Err(error));
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::InputEvent::{self, *};
+    use super::{Input, Key, Modifiers, MouseAction, MouseButton, MouseEvent};
+    use prelude::*;
+
+    fn parse(input: &[u8]) -> Vec<InputEvent> {
+        Input::new(input).collect()
+    }
+
+    fn key(key: Key) -> InputEvent {
+        Key(key, Modifiers::default())
+    }
+
+    fn alt(key: Key) -> InputEvent {
+        Key(key, Modifiers { alt: true, ..Modifiers::default() })
+    }
+
+    fn ctrl(key: Key) -> InputEvent {
+        Key(key, Modifiers::ctrl())
+    }
+
+    fn mouse(action: MouseAction, button: Option<MouseButton>, row: usize, column: usize) -> InputEvent {
+        Mouse(MouseEvent { action, button, row, column, modifiers: Modifiers::default() })
+    }
+
+    #[test]
+    fn escape_and_alt() {
+        assert_eq!(parse(b"\x1b"), [key(Key::Escape)]);
+        assert_eq!(parse(b"\x1ba"), [alt(Key::Char('a'))]);
+        assert_eq!(parse("\x1bé".as_bytes()), [alt(Key::Char('é'))]);
+        assert_eq!(parse(b"\x1b\x1b[C"), [alt(Key::Right)]);
+        assert_eq!(parse(b"\x1bP\x1b]q"), [alt(Key::Char('P')), alt(Key::Char(']')), key(Key::Char('q'))]);
+    }
+
+    #[test]
+    fn unfinished_sequences_at_the_end() {
+        assert_eq!(parse(b"\x1b["), [alt(Key::Char('['))]);
+        assert_eq!(parse(b"\x1bO"), [alt(Key::Char('O'))]);
+    }
+
+    #[test]
+    fn keys() {
+        assert_eq!(parse(b"\x03"), [ctrl(Key::Char('c'))]);
+        assert_eq!(parse(b"\x7f"), [key(Key::Backspace)]);
+        assert_eq!(parse(b"\x1bOP"), [key(Key::F(1))]);
+        assert_eq!(parse(b"\x1b[1;5B"), [ctrl(Key::Down)]);
+        assert_eq!(parse(b"\x1b[15;2~"), [Key(Key::F(5), Modifiers { shift: true, ..Modifiers::default() })]);
+        assert_eq!(parse(b"\x1b[Z"), [Key(Key::Tab, Modifiers { shift: true, ..Modifiers::default() })]);
+        assert_eq!(parse(b"\x1b[27;3;13~"), [alt(Key::Enter)]);
+        assert_eq!(parse(b"\x1b[97;5u"), [ctrl(Key::Char('a'))]);
+    }
+
+    #[test]
+    fn cursor_position_and_modified_f3() {
+        assert_eq!(parse(b"\x1b[12;40R"), [CursorPosition { row: 11, column: 39 }]);
+        assert_eq!(parse(b"\x1b[1;5R"), [CursorPosition { row: 0, column: 4 }]);
+    }
+
+    #[test]
+    fn sgr_mouse() {
+        use super::MouseAction::*;
+        use super::MouseButton::*;
+
+        assert_eq!(parse(b"\x1b[<0;10;5M"), [mouse(Press, Some(Left), 4, 9)]);
+        assert_eq!(parse(b"\x1b[<0;10;5m"), [mouse(Release, Some(Left), 4, 9)]);
+        assert_eq!(parse(b"\x1b[<35;11;5M"), [mouse(Move, None, 4, 10)]);
+        assert_eq!(parse(b"\x1b[<65;1;1M"), [mouse(Press, Some(WheelDown), 0, 0)]);
+
+        match parse(b"\x1b[<16;3;4M")[..] {
+            [Mouse(event)] => assert!(event.modifiers.ctrl),
+            ref other => panic!("not a mouse event: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn x10_and_urxvt_mouse() {
+        use super::MouseAction::*;
+        use super::MouseButton::*;
+
+        assert_eq!(parse(b"\x1b[M !\""), [mouse(Press, Some(Left), 1, 0)]);
+        assert_eq!(parse(b"\x1b[M#\xff\xff"), [mouse(Release, None, 222, 222)]);
+        assert_eq!(parse(b"\x1b[32;5;6M"), [mouse(Press, Some(Left), 5, 4)]);
+    }
+
+    #[test]
+    fn bracketed_paste() {
+        let events = parse(b"\x1b[200~hello\r\n\x1b[31mworld\x1b[201~z");
+        assert_eq!(events, [PasteStart, Paste("hello\r\nworld".into()), PasteEnd, key(Key::Char('z'))]);
+    }
+
+    #[test]
+    fn focus() {
+        assert_eq!(parse(b"\x1b[I\x1b[O"), [FocusGained, FocusLost]);
+    }
+}
@@ -0,0 +1,100 @@
+//! The output interface of terminals.
+//!
+//! With the `std` feature this is `std::io`, otherwise it is a minimal
+//! replacement with the same shape, so terminals can be written once for both.
+
+#[cfg(feature = "std")]
+pub use std::io::{Error, Result, Write};
+
+#[cfg(not(feature = "std"))]
+pub use self::core_io::{Error, Result, Write};
+
+#[cfg(not(feature = "std"))]
+mod core_io {
+    use core::fmt;
+
+    /// The error of a terminal without `std`
+    #[derive(Copy,Clone,Debug,PartialEq,Eq,Hash)]
+    pub enum Error {
+        /// The output could not be written completely
+        WriteZero,
+        /// A formatting implementation returned an error
+        Format,
+        /// An error specific to the terminal
+        Other(&'static str),
+    }
+
+    impl fmt::Display for Error {
+        fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            match *self {
+                Error::WriteZero => f.write_str("failed to write whole buffer"),
+                Error::Format => f.write_str("formatter error"),
+                Error::Other(message) => f.write_str(message),
+            }
+        }
+    }
+
+    pub type Result<T> = ::core::result::Result<T, Error>;
+
+    /// A byte sink, like `std::io::Write`
+    pub trait Write {
+        fn write(&mut self, buf: &[u8]) -> Result<usize>;
+
+        fn flush(&mut self) -> Result<()>;
+
+        fn write_all(&mut self, mut buf: &[u8]) -> Result<()> {
+            while !buf.is_empty() {
+                match self.write(buf)? {
+                    0 => return Err(Error::WriteZero),
+                    n => buf = &buf[n..],
+                }
+            }
+
+            Ok(())
+        }
+
+        fn write_fmt(&mut self, args: fmt::Arguments) -> Result<()> {
+            struct Adapter<'a, W: Write + ?Sized + 'a> {
+                writer: &'a mut W,
+                error: Option<Error>,
+            }
+
+            impl<'a, W: Write + ?Sized> fmt::Write for Adapter<'a, W> {
+                fn write_str(&mut self, s: &str) -> fmt::Result {
+                    self.writer.write_all(s.as_bytes()).map_err(|error| {
+                        self.error = Some(error);
+                        fmt::Error
+                    })
+                }
+            }
+
+            let mut adapter = Adapter { writer: self, error: None };
+
+            match fmt::write(&mut adapter, args) {
+                Ok(()) => Ok(()),
+                Err(_) => Err(adapter.error.unwrap_or(Error::Format)),
+            }
+        }
+    }
+
+    impl<W: Write + ?Sized> Write for &mut W {
+        fn write(&mut self, buf: &[u8]) -> Result<usize> {
+            (**self).write(buf)
+        }
+
+        fn flush(&mut self) -> Result<()> {
+            (**self).flush()
+        }
+    }
+
+    impl Write for ::alloc::vec::Vec<u8> {
+        fn write(&mut self, buf: &[u8]) -> Result<usize> {
+            self.extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> Result<()> {
+            Ok(())
+        }
+    }
+}
@@ -1,22 +1,211 @@
+#![cfg_attr(not(feature = "std"), no_std)]
+
+#[cfg(feature = "std")] extern crate core;
+#[macro_use] extern crate alloc;
 extern crate vte;
 #[macro_use] extern crate log;
+#[cfg(feature = "tokio")] extern crate tokio;
 
 // TODO: Improve code interpretation
-// TODO: Handle I/O errors somehow
 
-use vte::{Parser,Perform};
-use std::io::{self, Write};
+use vte::{Params,Parser,Perform};
+use alloc::boxed::Box;
+use core::{fmt, str};
+use io::Write;
+use prelude::*;
+
+pub mod io;
+
+mod screen;
+mod plain_text;
+mod ansi_writer;
+#[cfg(feature = "std")] mod downsample;
+mod sgr;
+mod events;
+mod input;
+mod sanitize;
+mod spans;
+mod graphic_state;
+mod sixel;
+#[cfg(feature = "tokio")] mod async_write;
+
+pub use screen::{Screen, Cell};
+pub use plain_text::PlainTextWriter;
+pub use ansi_writer::AnsiWriter;
+#[cfg(feature = "std")] pub use downsample::{Downsample, ColorDepth};
+pub use sgr::SgrAttr;
+pub use events::{Event, Events};
+#[cfg(feature = "std")] pub use events::ReadEvents;
+pub use input::{Input, InputEvent, Key, Modifiers, MouseAction, MouseButton, MouseEvent};
+#[cfg(feature = "std")] pub use input::ReadInput;
+pub use sanitize::{Allowed, Sanitizer};
+pub use graphic_state::GraphicState;
+pub use spans::{parse_spans, Hyperlink, Span, SpanCollector, StyleState};
+pub use sixel::SixelImage;
+#[cfg(feature = "tokio")] pub use async_write::{AsyncBuffer, AsyncTerminal};
+
+/// The `alloc` items that `std` has in its prelude
+mod prelude {
+    pub use alloc::string::{String, ToString};
+    pub use alloc::vec::Vec;
+}
 
 pub trait Terminal: Write {
     fn print(&mut self, ch: char) -> io::Result<()>;
+
+    /// Prints a run of text without control characters, by default char by char
+    fn print_str(&mut self, text: &str) -> io::Result<()> {
+        for ch in text.chars() {
+            self.print(ch)?;
+        }
+
+        Ok(())
+    }
+
     fn set_fg_color(&mut self, color: Color) -> io::Result<()>;
     fn set_bg_color(&mut self, color: Color) -> io::Result<()>;
     fn reset_style(&mut self) -> io::Result<()>;
     fn add_style(&mut self, style: Style) -> io::Result<()>;
+
+    fn remove_style(&mut self, _style: Style) -> io::Result<()> {
+        Ok(())
+    }
+
+    fn reset_fg_color(&mut self) -> io::Result<()> {
+        self.set_fg_color(Color::Default)
+    }
+
+    fn reset_bg_color(&mut self) -> io::Result<()> {
+        self.set_bg_color(Color::Default)
+    }
+
+    /// Sets the underline color (SGR 58), `Color::Default` restores the default (SGR 59)
+    fn set_underline_color(&mut self, _color: Color) -> io::Result<()> {
+        Ok(())
+    }
+
+    /// Moves the cursor `n` rows up (CUU)
+    fn cursor_up(&mut self, _n: usize) -> io::Result<()> {
+        Ok(())
+    }
+
+    /// Moves the cursor `n` rows down (CUD)
+    fn cursor_down(&mut self, _n: usize) -> io::Result<()> {
+        Ok(())
+    }
+
+    /// Moves the cursor `n` columns to the right (CUF)
+    fn cursor_forward(&mut self, _n: usize) -> io::Result<()> {
+        Ok(())
+    }
+
+    /// Moves the cursor `n` columns to the left (CUB)
+    fn cursor_backward(&mut self, _n: usize) -> io::Result<()> {
+        Ok(())
+    }
+
+    /// Moves the cursor to the zero-based `row` and `column` (CUP/HVP)
+    fn set_cursor_position(&mut self, _row: usize, _column: usize) -> io::Result<()> {
+        Ok(())
+    }
+
+    /// Moves the cursor to the zero-based `column` of the current row (CHA/HPA)
+    fn set_cursor_column(&mut self, _column: usize) -> io::Result<()> {
+        Ok(())
+    }
+
+    /// Moves the cursor to the zero-based `row`, keeping the column (VPA)
+    fn set_cursor_row(&mut self, _row: usize) -> io::Result<()> {
+        Ok(())
+    }
+
+    /// Erases part of the screen (ED).
+    /// Erased cells take the currently active background color.
+    fn erase_in_display(&mut self, _mode: EraseMode) -> io::Result<()> {
+        Ok(())
+    }
+
+    /// Erases part of the current line (EL).
+    /// Erased cells take the currently active background color.
+    fn erase_in_line(&mut self, _mode: EraseMode) -> io::Result<()> {
+        Ok(())
+    }
+
+    /// Starts a hyperlink to `uri` (OSC 8), ending any previous one.
+    /// Cells with the same `id` belong to the same link.
+    fn start_hyperlink(&mut self, _uri: &str, _id: Option<&str>) -> io::Result<()> {
+        Ok(())
+    }
+
+    /// Ends the current hyperlink, if any
+    fn end_hyperlink(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+
+    /// Sets the window title (OSC 0/2, or popped from the title stack)
+    fn set_title(&mut self, _title: &str) -> io::Result<()> {
+        Ok(())
+    }
+
+    /// Sets the icon name (OSC 0/1, or popped from the title stack)
+    fn set_icon_name(&mut self, _name: &str) -> io::Result<()> {
+        Ok(())
+    }
+
+    /// Moves the cursor to the start of the line (CR)
+    fn carriage_return(&mut self) -> io::Result<()> {
+        self.write_all(b"\r")
+    }
+
+    /// Moves the cursor to the next line (LF, VT and FF)
+    fn line_feed(&mut self) -> io::Result<()> {
+        self.write_all(b"\n")
+    }
+
+    /// Moves the cursor one column to the left (BS)
+    fn backspace(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+
+    /// Moves the cursor to the next tab stop (HT)
+    fn tab(&mut self) -> io::Result<()> {
+        self.write_all(b"\t")
+    }
+
+    /// Rings the bell (BEL)
+    fn bell(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+
+    /// Any other C0 control character, or a C1 control character (0x80 to 0x9F),
+    /// whether it was UTF-8 encoded or a lone byte
+    fn control(&mut self, _byte: u8) -> io::Result<()> {
+        Ok(())
+    }
+
+    /// A complete device control string (DCS), e.g. a Sixel image when `action` is `q`.
+    /// `data` is everything between the final character and the string terminator.
+    fn device_control(&mut self, _params: &[i64], _intermediates: &[u8], _action: char, _data: &[u8]) -> io::Result<()> {
+        Ok(())
+    }
+
+    /// The zero-based `(row, column)` of the cursor, used to answer cursor position reports (DSR 6).
+    /// Terminals that do not keep track of it return `None`, and the query goes unanswered.
+    fn query_cursor_position(&self) -> Option<(usize, usize)> {
+        None
+    }
+
+    /// The `(rows, columns)` of the text area, used to answer `CSI 18 t` and `CSI 19 t`.
+    /// Terminals without a size return `None`, and the query goes unanswered.
+    fn query_size(&self) -> Option<(usize, usize)> {
+        None
+    }
 }
 
 #[derive(Copy,Clone,Debug,PartialEq,Eq,Hash,PartialOrd,Ord)]
 pub enum Color {
+    /// The terminal's default foreground or background color
+    Default,
     Black,
     Red,
     Green,
@@ -33,6 +222,10 @@ pub enum Color {
     BrightMagenta,
     BrightCyan,
     BrightWhite,
+    /// An entry of the 256 color palette (`38;5;n` / `48;5;n`)
+    Indexed(u8),
+    /// A 24-bit truecolor value (`38;2;r;g;b` / `48;2;r;g;b`)
+    Rgb(u8, u8, u8),
 }
 
 impl Color {
@@ -63,6 +256,80 @@ impl Color {
             _ => self
         }
     }
+
+    /// Converts a 256 color palette index into a color.
+    /// The first 16 entries map to the basic colors.
+    pub fn from_index(index: u8) -> Color {
+        use self::Color::*;
+        match index {
+            0 => Black,
+            1 => Red,
+            2 => Green,
+            3 => Yellow,
+            4 => Blue,
+            5 => Magenta,
+            6 => Cyan,
+            7 => White,
+            8 => BrightBlack,
+            9 => BrightRed,
+            10 => BrightGreen,
+            11 => BrightYellow,
+            12 => BrightBlue,
+            13 => BrightMagenta,
+            14 => BrightCyan,
+            15 => BrightWhite,
+            _ => Indexed(index),
+        }
+    }
+
+    /// Returns the RGB value of the color, using the xterm default palette
+    /// for the basic and indexed colors.
+    /// The default color has no RGB value as it depends on the terminal.
+    pub fn rgb(self) -> Option<(u8, u8, u8)> {
+        use self::Color::*;
+        Some(match self {
+            Default => return None,
+            Black => (0, 0, 0),
+            Red => (205, 0, 0),
+            Green => (0, 205, 0),
+            Yellow => (205, 205, 0),
+            Blue => (0, 0, 238),
+            Magenta => (205, 0, 205),
+            Cyan => (0, 205, 205),
+            White => (229, 229, 229),
+            BrightBlack => (127, 127, 127),
+            BrightRed => (255, 0, 0),
+            BrightGreen => (0, 255, 0),
+            BrightYellow => (255, 255, 0),
+            BrightBlue => (92, 92, 255),
+            BrightMagenta => (255, 0, 255),
+            BrightCyan => (0, 255, 255),
+            BrightWhite => (255, 255, 255),
+            Indexed(index) if index < 16 => return Color::from_index(index).rgb(),
+            Indexed(index) if index < 232 => {
+                let level = |n: u8| if n == 0 { 0 } else { 55 + n * 40 };
+                let index = index - 16;
+                (level(index / 36), level(index / 6 % 6), level(index % 6))
+            },
+            Indexed(index) => {
+                let gray = 8 + (index - 232) * 10;
+                (gray, gray, gray)
+            },
+            Rgb(r, g, b) => (r, g, b),
+        })
+    }
+
+    /// Approximates the color with the perceptually closest of the 16 basic colors.
+    /// Requires the `std` feature for floating point math.
+    #[cfg(feature = "std")]
+    pub fn to_basic(self) -> Color {
+        match self {
+            Color::Indexed(_) | Color::Rgb(..) => {
+                downsample::nearest(self, (0..16).map(Color::from_index))
+            },
+            _ => self,
+        }
+    }
 }
 
 #[derive(Copy,Clone,Debug,PartialEq,Eq,Hash,PartialOrd,Ord)]
@@ -71,11 +338,84 @@ pub enum Style {
     Faint,
     Italic,
     Underline,
+    /// SGR 21 or 4:2
+    DoubleUnderline,
+    /// SGR 4:3
+    CurlyUnderline,
+    /// SGR 4:4
+    DottedUnderline,
+    /// SGR 4:5
+    DashedUnderline,
     BlinkSlow,
     BlinkFast,
     Reverse,
     Hidden,
     Crossed,
+    /// One of the alternative fonts 1 to 9 (SGR 11-19)
+    AlternateFont(u8),
+    /// SGR 20
+    Fraktur,
+    /// SGR 51
+    Framed,
+    /// SGR 52
+    Encircled,
+    /// SGR 53
+    Overline,
+    /// SGR 73
+    Superscript,
+    /// SGR 74
+    Subscript,
+}
+
+/// The underline styles, at most one of them is active at a time
+pub(crate) const UNDERLINE_STYLES: &[Style] = &[
+    Style::Underline,
+    Style::DoubleUnderline,
+    Style::CurlyUnderline,
+    Style::DottedUnderline,
+    Style::DashedUnderline,
+];
+
+/// The alternative fonts, at most one of them is active at a time
+pub(crate) const ALTERNATE_FONTS: &[Style] = &[
+    Style::AlternateFont(1),
+    Style::AlternateFont(2),
+    Style::AlternateFont(3),
+    Style::AlternateFont(4),
+    Style::AlternateFont(5),
+    Style::AlternateFont(6),
+    Style::AlternateFont(7),
+    Style::AlternateFont(8),
+    Style::AlternateFont(9),
+];
+
+impl Style {
+    /// Returns whether this is one of the underline styles
+    pub fn is_underline(self) -> bool {
+        UNDERLINE_STYLES.contains(&self)
+    }
+}
+
+/// The region affected by an erase operation
+#[derive(Copy,Clone,Debug,PartialEq,Eq,Hash)]
+pub enum EraseMode {
+    /// From the cursor to the end, including the cursor position
+    ToEnd,
+    /// From the start to the cursor, including the cursor position
+    ToStart,
+    /// Everything
+    All,
+}
+
+impl EraseMode {
+    fn from_param(param: i64) -> Option<EraseMode> {
+        match param {
+            0 => Some(EraseMode::ToEnd),
+            1 => Some(EraseMode::ToStart),
+            2 => Some(EraseMode::All),
+            _ => None,
+        }
+    }
 }
 
 pub struct Shim<T: Terminal> {
@@ -87,113 +427,715 @@ impl<T: Terminal> Shim<T> {
     pub fn new(terminal: T) -> Self {
         Self {
             parser: Parser::new(),
-            terminal: VteTerm(terminal),
+            terminal: VteTerm::new(terminal),
+        }
+    }
+
+    pub fn get_ref(&self) -> &T {
+        &self.terminal.terminal
+    }
+
+    pub fn get_mut(&mut self) -> &mut T {
+        &mut self.terminal.terminal
+    }
+
+    pub fn into_inner(self) -> T {
+        self.terminal.terminal
+    }
+
+    /// Sets where answers to queries like device attributes (DA) and status reports (DSR) go,
+    /// usually the input of the program whose output is fed to the shim.
+    /// Without it, queries go unanswered.
+    pub fn set_reply_writer<W: Write + Send + 'static>(&mut self, replies: W) {
+        self.terminal.replies = Some(Box::new(replies));
+    }
+
+    /// Handles the start of `buf` without the parser, which is in the ground state,
+    /// and returns the remainder.
+    /// Printable text is passed on in one piece, anything else is left to the parser.
+    fn write_ground<'a>(&mut self, buf: &'a [u8]) -> &'a [u8] {
+        let text = printable_prefix(buf);
+
+        if !text.is_empty() {
+            let result = self.terminal.terminal.print_str(text);
+            self.terminal.record(result);
+            return &buf[text.len()..];
+        }
+
+        match buf[0] {
+            // C0 controls do not leave the ground state
+            byte @ 0x00..=0x1a | byte @ 0x1c..=0x1f => {
+                self.terminal.execute(byte);
+                &buf[1..]
+            },
+            0x1b => {
+                self.terminal.ground = false;
+                buf
+            },
+            // A C1 control, invalid UTF-8 or the start of a cut off character.
+            // The parser handles only that character, or it would take the rest of the text with it.
+            _ => {
+                let len = match str::from_utf8(&buf[..buf.len().min(4)]) {
+                    Err(ref error) if error.valid_up_to() == 0 => error.error_len(),
+                    _ => Some(2),
+                };
+
+                match len {
+                    Some(len) => {
+                        self.parser.advance(&mut self.terminal, &buf[..len]);
+                        &buf[len..]
+                    },
+                    None => {
+                        // The rest of the character follows in the next write
+                        self.parser.advance(&mut self.terminal, buf);
+                        self.terminal.ground = false;
+                        &[]
+                    },
+                }
+            },
         }
     }
 }
 
+/// The valid UTF-8 at the start of `buf` up to the first control character
+/// or a character that is cut off
+fn printable_prefix(buf: &[u8]) -> &str {
+    let end = buf.iter().position(|&byte| byte < 0x20).unwrap_or(buf.len());
+    let text = match str::from_utf8(&buf[..end]) {
+        Ok(text) => text,
+        Err(error) => str::from_utf8(&buf[..error.valid_up_to()]).unwrap_or_default(),
+    };
+
+    // C1 controls are encoded as 0xC2 0x80 to 0xC2 0x9F
+    if text.as_bytes().contains(&0xC2) {
+        if let Some(index) = text.find(|ch| ('\u{80}'..='\u{9f}').contains(&ch)) {
+            return &text[..index];
+        }
+    }
+
+    text
+}
+
 impl<T: Terminal> Write for Shim<T> {
+    /// Feeds `buf` to the parser.
+    /// Runs of plain text skip the parser and are printed with `Terminal::print_str`.
+    ///
+    /// Processing stops at the first byte whose handling made the terminal
+    /// return an error, and that error is returned.
+    /// Any partially parsed escape sequence is discarded at that point,
+    /// so the next write starts out in the ground state.
     fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
-        for &byte in buf {
-            self.parser.advance(&mut self.terminal, byte);
+        let mut rest = buf;
+
+        while !rest.is_empty() {
+            rest = if self.terminal.ground {
+                self.write_ground(rest)
+            } else {
+                let len = self.parser.advance_until_terminated(&mut self.terminal, rest);
+                &rest[len..]
+            };
+
+            if let Some(error) = self.terminal.error.take() {
+                self.parser = Parser::new();
+                self.terminal.ground = true;
+                return Err(error);
+            }
         }
-        
+
         Ok(buf.len())
     }
 
     fn flush(&mut self) -> io::Result<()> {
-        self.terminal.0.flush()
+        self.terminal.terminal.flush()
     }
 }
 
-struct VteTerm<T: Terminal>(T);
+/// The maximum number of entries on the title stack, same as xterm
+const TITLE_STACK_LIMIT: usize = 10;
+
+/// The maximum size of a device control string payload, larger ones are dropped
+pub(crate) const DCS_LIMIT: usize = 16 * 1024 * 1024;
+
+/// A device control string that is still being received
+struct DeviceControl {
+    params: Vec<i64>,
+    intermediates: Vec<u8>,
+    action: char,
+    data: Vec<u8>,
+}
+
+struct VteTerm<T: Terminal> {
+    terminal: T,
+    /// The first error returned by the terminal since it was last checked
+    error: Option<io::Error>,
+    title: String,
+    icon_name: String,
+    title_stack: Vec<String>,
+    icon_name_stack: Vec<String>,
+    device_control: Option<DeviceControl>,
+    replies: Option<Box<dyn Write + Send>>,
+    /// Set when the parser is known to be back in the ground state,
+    /// without a partially received character
+    ground: bool,
+}
 
 impl<T: Terminal> VteTerm<T> {
-    fn handle_formatting(&mut self, params: &[i64]) {
-        use self::Color::*;
-        use self::Style::*;
-
-        if params.is_empty() {
-            self.0.reset_style();
-        }
-
-        for &param in params {
-            match param {
-                0 => self.0.reset_style(),
-                1 => self.0.add_style(Bold),
-                2 => self.0.add_style(Faint),
-                3 => self.0.add_style(Italic),
-                4 => self.0.add_style(Underline),
-                5 => self.0.add_style(BlinkSlow),
-                6 => self.0.add_style(BlinkFast),
-                7 => self.0.add_style(Reverse),
-                8 => self.0.add_style(Hidden),
-                30 => self.0.set_fg_color(Black),
-                31 => self.0.set_fg_color(Red),
-                32 => self.0.set_fg_color(Green),
-                33 => self.0.set_fg_color(Yellow),
-                34 => self.0.set_fg_color(Blue),
-                35 => self.0.set_fg_color(Magenta),
-                36 => self.0.set_fg_color(Cyan),
-                37 => self.0.set_fg_color(White),
-                40 => self.0.set_bg_color(Black),
-                41 => self.0.set_bg_color(Red),
-                42 => self.0.set_bg_color(Green),
-                43 => self.0.set_bg_color(Yellow),
-                44 => self.0.set_bg_color(Blue),
-                45 => self.0.set_bg_color(Magenta),
-                46 => self.0.set_bg_color(Cyan),
-                47 => self.0.set_bg_color(White),
-                90 => self.0.set_fg_color(BrightBlack),
-                91 => self.0.set_fg_color(BrightRed),
-                92 => self.0.set_fg_color(BrightGreen),
-                93 => self.0.set_fg_color(BrightYellow),
-                94 => self.0.set_fg_color(BrightBlue),
-                95 => self.0.set_fg_color(BrightMagenta),
-                96 => self.0.set_fg_color(BrightCyan),
-                97 => self.0.set_fg_color(BrightWhite),
-                100 => self.0.set_bg_color(BrightBlack),
-                101 => self.0.set_bg_color(BrightRed),
-                102 => self.0.set_bg_color(BrightGreen),
-                103 => self.0.set_bg_color(BrightYellow),
-                104 => self.0.set_bg_color(BrightBlue),
-                105 => self.0.set_bg_color(BrightMagenta),
-                106 => self.0.set_bg_color(BrightCyan),
-                107 => self.0.set_bg_color(BrightWhite),
-                _ => {
-                    debug!("Unhandled SGR param: {}", param);
-                    Ok(())
+    fn new(terminal: T) -> Self {
+        Self {
+            terminal,
+            error: None,
+            title: String::new(),
+            icon_name: String::new(),
+            title_stack: Vec::new(),
+            icon_name_stack: Vec::new(),
+            device_control: None,
+            replies: None,
+            ground: true,
+        }
+    }
+
+    fn record(&mut self, result: io::Result<()>) {
+        if let Err(error) = result {
+            if self.error.is_none() {
+                self.error = Some(error);
+            }
+        }
+    }
+
+    /// Sends `reply` to the reply writer, if there is one.
+    /// A failing reply writer is dropped rather than failing the output,
+    /// which the terminal has handled fine.
+    fn reply(&mut self, reply: fmt::Arguments) {
+        let result = match self.replies {
+            // The program is waiting for it, hence the flush
+            Some(ref mut replies) => replies.write_fmt(reply).and_then(|()| replies.flush()),
+            None => return,
+        };
+
+        if let Err(error) = result {
+            warn!("Dropping the reply writer after an error: {}", error);
+            self.replies = None;
+        }
+    }
+
+    /// Handles `CSI Ps n` and `CSI ? Ps n`
+    fn handle_status_report(&mut self, params: &[i64], intermediates: &[u8]) -> io::Result<()> {
+        let private = if intermediates.is_empty() { "" } else { "?" };
+
+        match params.first().cloned().unwrap_or(0) {
+            5 if private.is_empty() => self.reply(format_args!("\x1b[0n")),
+            6 => {
+                if let Some((row, column)) = self.terminal.query_cursor_position() {
+                    self.reply(format_args!("\x1b[{}{};{}R", private, row + 1, column + 1));
                 }
-            };
+            },
+            _ => debug!("Unhandled status report: {:?} {:?}", params, intermediates),
+        }
+
+        Ok(())
+    }
+
+    /// Handles `CSI c` (DA1) and `CSI > c` (DA2)
+    fn handle_device_attributes(&mut self, params: &[i64], intermediates: &[u8]) -> io::Result<()> {
+        if params.first().cloned().unwrap_or(0) != 0 {
+            return Ok(());
+        }
+
+        match intermediates {
+            // A VT220 with ANSI colors
+            [] => self.reply(format_args!("\x1b[?62;22c")),
+            // A VT220 with firmware version 0
+            _ => self.reply(format_args!("\x1b[>1;0;0c")),
+        }
+
+        Ok(())
+    }
+
+    fn handle_formatting(&mut self, params: &[Vec<i64>]) -> io::Result<()> {
+        for attr in sgr::parse(params) {
+            match attr {
+                SgrAttr::Reset => self.terminal.reset_style(),
+                SgrAttr::AddStyle(style) => self.terminal.add_style(style),
+                SgrAttr::RemoveStyle(style) => self.terminal.remove_style(style),
+                SgrAttr::Foreground(Color::Default) => self.terminal.reset_fg_color(),
+                SgrAttr::Foreground(color) => self.terminal.set_fg_color(color),
+                SgrAttr::Background(Color::Default) => self.terminal.reset_bg_color(),
+                SgrAttr::Background(color) => self.terminal.set_bg_color(color),
+                SgrAttr::UnderlineColor(color) => self.terminal.set_underline_color(color),
+            }?;
+        }
+
+        Ok(())
+    }
+
+    /// Handles `OSC 8 ; params ; URI`.
+    /// The URI itself may contain semicolons, so it spans all remaining params.
+    fn handle_hyperlink(&mut self, params: &[&[u8]]) -> io::Result<()> {
+        if params.len() < 2 {
+            debug!("Malformed hyperlink: {:?}", params);
+            return Ok(());
+        }
+
+        let uri = params[1..].join(&b';');
+        let uri = String::from_utf8_lossy(&uri);
+
+        if uri.is_empty() {
+            return self.terminal.end_hyperlink();
+        }
+
+        let link_params = String::from_utf8_lossy(params[0]);
+        let id = link_params.split(':')
+            .filter_map(|param| {
+                let mut parts = param.splitn(2, '=');
+                match (parts.next(), parts.next()) {
+                    (Some("id"), Some(id)) if !id.is_empty() => Some(id),
+                    _ => None,
+                }
+            })
+            .next();
+
+        self.terminal.start_hyperlink(&uri, id)
+    }
+
+    /// Handles `OSC 0/1/2 ; text`
+    fn handle_title(&mut self, kind: &[u8], params: &[&[u8]]) -> io::Result<()> {
+        let text = params.join(&b';');
+        let text = String::from_utf8_lossy(&text).into_owned();
+
+        if kind != b"2" {
+            self.icon_name = text.clone();
+            self.terminal.set_icon_name(&self.icon_name)?;
+        }
+
+        if kind != b"1" {
+            self.title = text;
+            self.terminal.set_title(&self.title)?;
+        }
+
+        Ok(())
+    }
+
+    /// Handles the title stack operations and size reports of `CSI Ps ; Ps t`
+    fn handle_window_op(&mut self, params: &[i64]) -> io::Result<()> {
+        let which = params.get(1).cloned().unwrap_or(0);
+        let icon_name = which == 0 || which == 1;
+        let title = which == 0 || which == 2;
+
+        match params.first() {
+            Some(&22) => {
+                if icon_name {
+                    push_bounded(&mut self.icon_name_stack, self.icon_name.clone());
+                }
+
+                if title {
+                    push_bounded(&mut self.title_stack, self.title.clone());
+                }
+            },
+            Some(&23) => {
+                if icon_name {
+                    if let Some(name) = self.icon_name_stack.pop() {
+                        self.icon_name = name;
+                        self.terminal.set_icon_name(&self.icon_name)?;
+                    }
+                }
+
+                if title {
+                    if let Some(title) = self.title_stack.pop() {
+                        self.title = title;
+                        self.terminal.set_title(&self.title)?;
+                    }
+                }
+            },
+            // Text area and screen size in characters, which are the same here
+            Some(&op @ 18) | Some(&op @ 19) => {
+                if let Some((rows, columns)) = self.terminal.query_size() {
+                    self.reply(format_args!("\x1b[{};{};{}t", op - 10, rows, columns));
+                }
+            },
+            _ => debug!("Unhandled window operation: {:?}", params),
         }
+
+        Ok(())
+    }
+}
+
+fn push_bounded(stack: &mut Vec<String>, item: String) {
+    if stack.len() >= TITLE_STACK_LIMIT {
+        stack.remove(0);
+    }
+
+    stack.push(item);
+}
+
+/// Converts params, keeping the colon separated subparams of each param together
+pub(crate) fn param_groups(params: &Params) -> Vec<Vec<i64>> {
+    params.iter()
+        .map(|param| param.iter().map(|&value| i64::from(value)).collect())
+        .collect()
+}
+
+/// Converts params without subparams, `None` if any param has subparams
+pub(crate) fn flat_params(params: &Params) -> Option<Vec<i64>> {
+    params.iter()
+        .map(|param| match *param {
+            [value] => Some(i64::from(value)),
+            _ => None,
+        })
+        .collect()
+}
+
+/// Returns the param at `index`, falling back to `default`
+/// if it is missing or zero.
+fn param_or(params: &[i64], index: usize, default: usize) -> usize {
+    match params.get(index) {
+        Some(&param) if param > 0 => param as usize,
+        _ => default,
     }
 }
 
 impl<T: Terminal> Perform for VteTerm<T> {
     fn print(&mut self, ch: char) {
-        self.0.print(ch);
+        // Only happens in the ground state, e.g. when a cut off character got completed
+        self.ground = true;
+
+        // Text arrives in runs, which must not continue past an error
+        if self.error.is_some() {
+            return;
+        }
+
+        let result = self.terminal.print(ch);
+        self.record(result);
     }
 
     fn execute(&mut self, byte: u8) {
-        self.0.write(&[byte]);
+        let result = match byte {
+            0x07 => self.terminal.bell(),
+            0x08 => self.terminal.backspace(),
+            b'\t' => self.terminal.tab(),
+            b'\n' | 0x0b | 0x0c => self.terminal.line_feed(),
+            b'\r' => self.terminal.carriage_return(),
+            _ => self.terminal.control(byte),
+        };
+
+        self.record(result);
+    }
+
+    fn hook(&mut self, params: &Params, intermediates: &[u8], ignore: bool, action: char) {
+        let params = match flat_params(params) {
+            Some(flat) if !ignore => flat,
+            _ => {
+                debug!("Ignoring DCS: {:?} {:?} {:?} {:?}", params, intermediates, ignore, action);
+                return;
+            }
+        };
+
+        self.device_control = Some(DeviceControl {
+            params,
+            intermediates: intermediates.to_vec(),
+            action,
+            data: Vec::new(),
+        });
+    }
+
+    fn put(&mut self, byte: u8) {
+        let overflow = match self.device_control {
+            Some(ref mut dcs) if dcs.data.len() < DCS_LIMIT => {
+                dcs.data.push(byte);
+                false
+            },
+            Some(_) => true,
+            None => false,
+        };
+
+        if overflow {
+            debug!("Dropping DCS larger than {} bytes", DCS_LIMIT);
+            self.device_control = None;
+        }
+    }
+
+    fn unhook(&mut self) {
+        if let Some(dcs) = self.device_control.take() {
+            let result = self.terminal.device_control(&dcs.params, &dcs.intermediates, dcs.action, &dcs.data);
+            self.record(result);
+        }
+    }
+
+    fn osc_dispatch(&mut self, params: &[&[u8]], bell_terminated: bool) {
+        // Otherwise the ST escape sequence is still to come
+        self.ground = bell_terminated;
+
+        let result = match params.first() {
+            Some(&kind) if kind == b"0" || kind == b"1" || kind == b"2" => {
+                self.handle_title(kind, &params[1..])
+            },
+            Some(&b"8") => self.handle_hyperlink(&params[1..]),
+            _ => {
+                debug!("OSC: {:?}", params);
+                Ok(())
+            }
+        };
+
+        self.record(result);
     }
 
-    fn hook(&mut self, params: &[i64], intermediates: &[u8], ignore: bool) {}
+    fn csi_dispatch(&mut self, params: &Params, intermediates: &[u8], ignore: bool, cmd: char) {
+        self.ground = true;
 
-    fn put(&mut self, byte: u8) {}
+        // SGR is the only sequence with meaningful subparams
+        if cmd == 'm' && intermediates.is_empty() {
+            let result = self.handle_formatting(&param_groups(params));
+            self.record(result);
+            return;
+        }
+
+        let params = match flat_params(params) {
+            Some(params) => params,
+            None => {
+                debug!("Unhandled subparams: {:?} {:?}", params, cmd);
+                return;
+            }
+        };
+        let params = &params[..];
+
+        let result = match (cmd, intermediates) {
+            ('A', []) => self.terminal.cursor_up(param_or(params, 0, 1)),
+            ('B', []) => self.terminal.cursor_down(param_or(params, 0, 1)),
+            ('C', []) => self.terminal.cursor_forward(param_or(params, 0, 1)),
+            ('D', []) => self.terminal.cursor_backward(param_or(params, 0, 1)),
+            ('H', []) | ('f', []) => {
+                let row = param_or(params, 0, 1) - 1;
+                let column = param_or(params, 1, 1) - 1;
+                self.terminal.set_cursor_position(row, column)
+            },
+            ('G', []) | ('`', []) => self.terminal.set_cursor_column(param_or(params, 0, 1) - 1),
+            ('d', []) => self.terminal.set_cursor_row(param_or(params, 0, 1) - 1),
+            ('t', []) => self.handle_window_op(params),
+            ('n', []) | ('n', [b'?']) => self.handle_status_report(params, intermediates),
+            ('c', []) | ('c', [b'>']) => self.handle_device_attributes(params, intermediates),
+            ('J', []) | ('K', []) => {
+                let param = params.first().cloned().unwrap_or(0);
+
+                match EraseMode::from_param(param) {
+                    Some(mode) if cmd == 'J' => self.terminal.erase_in_display(mode),
+                    Some(mode) => self.terminal.erase_in_line(mode),
+                    None => {
+                        debug!("Unhandled erase mode: {} {:?}", param, cmd);
+                        Ok(())
+                    }
+                }
+            },
+            _ => {
+                debug!("CSI: {:?} {:?} {:?} {:?}", params, intermediates, ignore, cmd);
+                Ok(())
+            }
+        };
+
+        self.record(result);
+    }
+
+    fn esc_dispatch(&mut self, intermediates: &[u8], ignore: bool, byte: u8) {
+        self.ground = true;
+
+        // String terminator of an OSC or DCS, which has already been dispatched
+        if byte == b'\\' && intermediates.is_empty() {
+            return;
+        }
+
+        debug!("Esc: {:?} {:?} {:?}", intermediates, ignore, byte);
+    }
+
+    /// Stops the parser once it is back in the ground state,
+    /// so `Shim` can take over again
+    fn terminated(&self) -> bool {
+        self.error.is_some() || self.ground
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use io::{self, Write};
+    use prelude::*;
+    use {Color, Shim, Style, Terminal};
+    #[cfg(feature = "std")] use std::sync::atomic::{AtomicUsize, Ordering};
+    #[cfg(feature = "std")] use std::sync::{Arc, Mutex};
+    #[cfg(feature = "std")] use Screen;
+
+    #[cfg(feature = "std")]
+    fn error(message: &'static str) -> io::Error {
+        io::Error::other(message)
+    }
+
+    #[cfg(not(feature = "std"))]
+    fn error(message: &'static str) -> io::Error {
+        io::Error::Other(message)
+    }
+
+    /// Records printed text and colors, printing `x` and setting colors fail
+    #[derive(Default)]
+    struct Failing {
+        printed: String,
+        colors: usize,
+    }
+
+    impl Terminal for Failing {
+        fn print(&mut self, ch: char) -> io::Result<()> {
+            if ch == 'x' {
+                return Err(error("print"));
+            }
+
+            self.printed.push(ch);
+            Ok(())
+        }
+
+        fn set_fg_color(&mut self, _color: Color) -> io::Result<()> {
+            self.colors += 1;
+            Err(error("fg"))
+        }
+
+        fn set_bg_color(&mut self, _color: Color) -> io::Result<()> {
+            self.colors += 1;
+            Err(error("bg"))
+        }
+
+        fn reset_style(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+
+        fn add_style(&mut self, _style: Style) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl Write for Failing {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn stops_at_the_first_error() {
+        let mut shim = Shim::new(Failing::default());
+        let error = shim.write(b"abxcd\x1b[31m").unwrap_err();
+
+        assert_eq!(error.to_string(), "print");
+        assert_eq!(shim.into_inner().printed, "ab");
+    }
 
-    fn unhook(&mut self) {}
+    #[test]
+    fn first_error_of_a_sequence_wins() {
+        let mut shim = Shim::new(Failing::default());
+        let error = shim.write(b"a\x1b[31;41mb").unwrap_err();
+        let terminal = shim.into_inner();
 
-    fn osc_dispatch(&mut self, params: &[&[u8]]) {}
+        assert_eq!(error.to_string(), "fg");
+        assert_eq!(terminal.colors, 1);
+        assert_eq!(terminal.printed, "a");
+    }
+
+    #[test]
+    fn next_write_starts_in_ground_state() {
+        let mut shim = Shim::new(Failing::default());
+        assert!(shim.write(b"\x1b[1mx\x1b[3").is_err());
+        assert_eq!(shim.write(b"1mb").unwrap(), 3);
+        assert_eq!(shim.into_inner().printed, "1mb");
+    }
+
+    #[test]
+    #[cfg(feature = "std")]
+    fn copy_fails() {
+        let mut shim = Shim::new(Failing::default());
+        assert!(::std::io::copy(&mut &b"abxcd"[..], &mut shim).is_err());
+        assert_eq!(shim.into_inner().printed, "ab");
+    }
+
+    /// Collects replies where the test can still see them after handing the writer to a shim
+    #[cfg(feature = "std")]
+    #[derive(Clone, Default)]
+    struct Replies(Arc<Mutex<Vec<u8>>>);
+
+    #[cfg(feature = "std")]
+    impl Write for Replies {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().extend_from_slice(buf);
+            Ok(buf.len())
+        }
 
-    fn csi_dispatch(&mut self, params: &[i64], intermediates: &[u8], ignore: bool, cmd: char) {
-        match cmd {
-            'm' => self.handle_formatting(params),
-            _ => debug!("CSI: {:?} {:?} {:?} {:?}", params, intermediates, ignore, cmd),
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
         }
     }
 
-    fn esc_dispatch( &mut self, params: &[i64], intermediates: &[u8], ignore: bool, byte: u8) {
-        debug!("Esc: {:?} {:?} {:?} {:?}", params, intermediates, ignore, byte);
+    #[cfg(feature = "std")]
+    fn replies_to(input: &[u8]) -> String {
+        let replies = Replies::default();
+        let mut shim = Shim::new(Screen::new(80, 24));
+        shim.set_reply_writer(replies.clone());
+        shim.write_all(input).unwrap();
+
+        let bytes = replies.0.lock().unwrap().clone();
+        String::from_utf8(bytes).unwrap()
+    }
+
+    #[test]
+    #[cfg(feature = "std")]
+    fn status_reports() {
+        assert_eq!(replies_to(b"\x1b[5n"), "\x1b[0n");
+        assert_eq!(replies_to(b"\x1b[?5n"), "");
+        assert_eq!(replies_to(b"ab\r\n\x1b[6n"), "\x1b[2;1R");
+        assert_eq!(replies_to(b"abc\x1b[?6n"), "\x1b[?1;4R");
+    }
+
+    #[test]
+    #[cfg(feature = "std")]
+    fn device_attributes() {
+        assert_eq!(replies_to(b"\x1b[c"), "\x1b[?62;22c");
+        assert_eq!(replies_to(b"\x1b[0c"), "\x1b[?62;22c");
+        assert_eq!(replies_to(b"\x1b[>c"), "\x1b[>1;0;0c");
+        assert_eq!(replies_to(b"\x1b[1c"), "");
+    }
+
+    #[test]
+    #[cfg(feature = "std")]
+    fn size_reports() {
+        assert_eq!(replies_to(b"\x1b[18t"), "\x1b[8;24;80t");
+        assert_eq!(replies_to(b"\x1b[19t"), "\x1b[9;24;80t");
+    }
+
+    #[test]
+    #[cfg(feature = "std")]
+    fn queries_without_reply_writer() {
+        let mut shim = Shim::new(Screen::new(80, 24));
+        shim.write_all(b"a\x1b[6n\x1b[cb").unwrap();
+        assert_eq!(shim.into_inner().text(), "ab");
+    }
+
+    #[test]
+    #[cfg(feature = "std")]
+    fn failing_reply_writer_is_dropped() {
+        struct BrokenPipe(Arc<AtomicUsize>);
+
+        impl Write for BrokenPipe {
+            fn write(&mut self, _buf: &[u8]) -> io::Result<usize> {
+                self.0.fetch_add(1, Ordering::SeqCst);
+                Err(::std::io::ErrorKind::BrokenPipe.into())
+            }
+
+            fn flush(&mut self) -> io::Result<()> {
+                Ok(())
+            }
+        }
+
+        let writes = Arc::default();
+        let mut shim = Shim::new(Screen::new(80, 24));
+        shim.set_reply_writer(BrokenPipe(Arc::clone(&writes)));
+
+        assert_eq!(shim.write(b"ab\x1b[6ncd").unwrap(), 8);
+        assert_eq!(shim.write(b"\x1b[5n").unwrap(), 4);
+        assert_eq!(writes.load(Ordering::SeqCst), 1);
+        assert_eq!(shim.into_inner().text(), "abcd");
     }
 }
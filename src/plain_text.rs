@@ -0,0 +1,243 @@
+use io::{self, Write};
+use prelude::*;
+use {Color, EraseMode, Style, Terminal};
+
+/// How far past the end of the current line the cursor can move in overwrite mode,
+/// so cheap cursor movement cannot pad a line with huge amounts of spaces
+const MAX_LINE_LENGTH: usize = 4096;
+
+/// A terminal that drops all styling and writes the plain text.
+///
+/// With overwrites enabled, carriage returns, backspaces and cursor movement
+/// within the current line overwrite previous output, so e.g. progress bars
+/// collapse into their final state.
+/// Line feeds, carriage returns, tabs and backspaces are kept,
+/// all other control characters are dropped.
+/// The last line is only written by `finish`.
+pub struct PlainTextWriter<W: Write> {
+    writer: W,
+    overwrite: bool,
+    line: Vec<char>,
+    column: usize,
+}
+
+impl<W: Write> PlainTextWriter<W> {
+    pub fn new(writer: W) -> Self {
+        Self {
+            writer,
+            overwrite: false,
+            line: Vec::new(),
+            column: 0,
+        }
+    }
+
+    /// Sets whether `\r`, `\b` and in-line cursor movement overwrite previous output
+    pub fn set_overwrite(&mut self, overwrite: bool) {
+        self.overwrite = overwrite;
+    }
+
+    pub fn get_ref(&self) -> &W {
+        &self.writer
+    }
+
+    pub fn get_mut(&mut self) -> &mut W {
+        &mut self.writer
+    }
+
+    /// Writes the pending line and returns the inner writer
+    pub fn finish(mut self) -> io::Result<W> {
+        self.write_line()?;
+        self.writer.flush()?;
+        Ok(self.writer)
+    }
+
+    fn write_line(&mut self) -> io::Result<()> {
+        let line = self.line.drain(..).collect::<String>();
+        self.column = 0;
+        self.writer.write_all(line.trim_end_matches(' ').as_bytes())
+    }
+
+    fn put(&mut self, ch: char) {
+        if self.column < self.line.len() {
+            self.line[self.column] = ch;
+        } else {
+            self.line.resize(self.column, ' ');
+            self.line.push(ch);
+        }
+
+        self.column += 1;
+    }
+
+    fn move_to(&mut self, column: usize) {
+        self.column = column.min(MAX_LINE_LENGTH.max(self.line.len()));
+    }
+}
+
+impl<W: Write> Terminal for PlainTextWriter<W> {
+    fn print(&mut self, ch: char) -> io::Result<()> {
+        if self.overwrite {
+            self.put(ch);
+            Ok(())
+        } else {
+            write!(self.writer, "{}", ch)
+        }
+    }
+
+    fn print_str(&mut self, text: &str) -> io::Result<()> {
+        if self.overwrite {
+            text.chars().for_each(|ch| self.put(ch));
+            Ok(())
+        } else {
+            self.writer.write_all(text.as_bytes())
+        }
+    }
+
+    fn set_fg_color(&mut self, _color: Color) -> io::Result<()> {
+        Ok(())
+    }
+
+    fn set_bg_color(&mut self, _color: Color) -> io::Result<()> {
+        Ok(())
+    }
+
+    fn reset_style(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+
+    fn add_style(&mut self, _style: Style) -> io::Result<()> {
+        Ok(())
+    }
+
+    fn cursor_forward(&mut self, n: usize) -> io::Result<()> {
+        if self.overwrite {
+            let column = self.column.saturating_add(n);
+            self.move_to(column);
+        }
+        Ok(())
+    }
+
+    fn cursor_backward(&mut self, n: usize) -> io::Result<()> {
+        if self.overwrite {
+            self.column = self.column.saturating_sub(n);
+        }
+        Ok(())
+    }
+
+    fn set_cursor_column(&mut self, column: usize) -> io::Result<()> {
+        if self.overwrite {
+            self.move_to(column);
+        }
+        Ok(())
+    }
+
+    fn erase_in_line(&mut self, mode: EraseMode) -> io::Result<()> {
+        if !self.overwrite {
+            return Ok(());
+        }
+
+        match mode {
+            EraseMode::ToEnd => self.line.truncate(self.column),
+            EraseMode::ToStart => {
+                let end = (self.column + 1).min(self.line.len());
+                for ch in &mut self.line[..end] {
+                    *ch = ' ';
+                }
+            },
+            EraseMode::All => self.line.clear(),
+        }
+
+        Ok(())
+    }
+
+    fn carriage_return(&mut self) -> io::Result<()> {
+        if self.overwrite {
+            self.column = 0;
+            Ok(())
+        } else {
+            self.writer.write_all(b"\r")
+        }
+    }
+
+    fn line_feed(&mut self) -> io::Result<()> {
+        if self.overwrite {
+            self.write_line()?;
+        }
+
+        self.writer.write_all(b"\n")
+    }
+
+    fn backspace(&mut self) -> io::Result<()> {
+        if self.overwrite {
+            self.column = self.column.saturating_sub(1);
+            Ok(())
+        } else {
+            self.writer.write_all(b"\x08")
+        }
+    }
+
+    fn tab(&mut self) -> io::Result<()> {
+        if self.overwrite {
+            self.put('\t');
+            Ok(())
+        } else {
+            self.writer.write_all(b"\t")
+        }
+    }
+}
+
+impl<W: Write> Write for PlainTextWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.writer.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{PlainTextWriter, MAX_LINE_LENGTH};
+    use io::Write;
+    use prelude::*;
+    use Shim;
+
+    fn convert(input: &[u8], overwrite: bool) -> String {
+        let mut writer = PlainTextWriter::new(Vec::new());
+        writer.set_overwrite(overwrite);
+        let mut shim = Shim::new(writer);
+        shim.write_all(input).unwrap();
+        String::from_utf8(shim.into_inner().finish().unwrap()).unwrap()
+    }
+
+    #[test]
+    fn keeps_controls_without_overwrite() {
+        assert_eq!(convert(b"\x1b[1mab\rc\x08d\x07\n", false), "ab\rc\x08d\n");
+    }
+
+    #[test]
+    fn carriage_return_overwrites() {
+        assert_eq!(convert(b" 10%\r 50%\r100%\ndone", true), "100%\ndone");
+        assert_eq!(convert(b"abcdef\rxy\n", true), "xycdef\n");
+    }
+
+    #[test]
+    fn backspace_overwrites() {
+        assert_eq!(convert(b"abc\x08\x08X\n", true), "aXc\n");
+        assert_eq!(convert(b"a\x08\x08\x08b", true), "b");
+    }
+
+    #[test]
+    fn cursor_movement_and_erase() {
+        assert_eq!(convert(b"abcdef\x1b[3D\x1b[K\n", true), "abc\n");
+        assert_eq!(convert(b"a\x1b[3Cb\n", true), "a   b\n");
+        assert_eq!(convert(b"spinner |\x1b[1G\x1b[2Kdone\n", true), "done\n");
+    }
+
+    #[test]
+    fn cursor_movement_is_bounded() {
+        let output = convert(&b"\x1b[65535Cx".repeat(100), true);
+        assert!(output.len() <= MAX_LINE_LENGTH + 100);
+        assert!(output.ends_with("xxx"));
+    }
+}
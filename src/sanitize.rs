@@ -0,0 +1,290 @@
+use io::{self, Write};
+use {AnsiWriter, Color, EraseMode, Style, Terminal};
+
+/// The kinds of sequences a `Sanitizer` passes on
+#[derive(Copy,Clone,Debug,Default,PartialEq,Eq,Hash)]
+pub struct Allowed {
+    /// Colors and text styles (SGR), except concealed text and fonts
+    pub sgr: bool,
+    /// Cursor movement (CUU, CUD, CUF, CUB, CUP, CHA, VPA) and backspaces
+    pub cursor: bool,
+    /// Erasing the display or line (ED, EL)
+    pub erase: bool,
+    /// Hyperlinks (OSC 8), whose text need not match their target
+    pub hyperlinks: bool,
+}
+
+impl Allowed {
+    /// Nothing but text, line feeds, carriage returns and tabs
+    pub const TEXT: Allowed = Allowed { sgr: false, cursor: false, erase: false, hyperlinks: false };
+    /// Colored text, e.g. for logs
+    pub const SGR: Allowed = Allowed { sgr: true, ..Allowed::TEXT };
+    /// Colored text that may move the cursor and erase the display or line
+    /// to redraw parts of the screen, e.g. for progress bars
+    pub const SGR_AND_CURSOR: Allowed = Allowed { sgr: true, cursor: true, erase: true, hyperlinks: false };
+}
+
+/// A terminal that makes untrusted output safe to display in a real terminal.
+///
+/// Only the `Allowed` sequences are written, re-encoded by an `AnsiWriter`
+/// rather than copied, so nothing else can slip through in their params.
+/// Everything else, like clipboard access (OSC 52), titles, queries,
+/// and device control strings (DCS), is dropped.
+/// Control characters other than line feeds, carriage returns and tabs
+/// are replaced by visible symbols.
+///
+/// Feed it through a `Shim`: `Shim::new(Sanitizer::new(writer, Allowed::SGR))`.
+/// Use `finish` to reset the attributes at the end of the output.
+pub struct Sanitizer<W: Write> {
+    writer: AnsiWriter<W>,
+    allowed: Allowed,
+}
+
+impl<W: Write> Sanitizer<W> {
+    pub fn new(writer: W, allowed: Allowed) -> Self {
+        Self {
+            writer: AnsiWriter::new(writer),
+            allowed,
+        }
+    }
+
+    pub fn get_ref(&self) -> &W {
+        self.writer.get_ref()
+    }
+
+    pub fn get_mut(&mut self) -> &mut W {
+        self.writer.get_mut()
+    }
+
+    /// Resets the attributes if needed and returns the inner writer
+    pub fn finish(self) -> io::Result<W> {
+        self.writer.finish()
+    }
+
+    fn replace_control(&mut self, byte: u8) -> io::Result<()> {
+        self.writer.print(control_picture(byte))
+    }
+}
+
+/// Styles that hide text or change how it looks beyond recognition
+fn is_disguising(style: Style) -> bool {
+    matches!(style, Style::Hidden | Style::AlternateFont(_) | Style::Fraktur)
+}
+
+/// A visible stand-in for a control character, from the Control Pictures block
+fn control_picture(byte: u8) -> char {
+    match byte {
+        0x00..=0x1f => char::from_u32(0x2400 + u32::from(byte)).unwrap_or('\u{fffd}'),
+        0x7f => '\u{2421}',
+        _ => '\u{fffd}',
+    }
+}
+
+impl<W: Write> Terminal for Sanitizer<W> {
+    fn print(&mut self, ch: char) -> io::Result<()> {
+        match ch {
+            '\x7f' => self.replace_control(0x7f),
+            ch => self.writer.print(ch),
+        }
+    }
+
+    fn print_str(&mut self, text: &str) -> io::Result<()> {
+        // DEL is the only control character that gets printed
+        for (index, part) in text.split('\x7f').enumerate() {
+            if index > 0 {
+                self.replace_control(0x7f)?;
+            }
+
+            if !part.is_empty() {
+                self.writer.print_str(part)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    fn set_fg_color(&mut self, color: Color) -> io::Result<()> {
+        if self.allowed.sgr { self.writer.set_fg_color(color) } else { Ok(()) }
+    }
+
+    fn set_bg_color(&mut self, color: Color) -> io::Result<()> {
+        if self.allowed.sgr { self.writer.set_bg_color(color) } else { Ok(()) }
+    }
+
+    fn set_underline_color(&mut self, color: Color) -> io::Result<()> {
+        if self.allowed.sgr { self.writer.set_underline_color(color) } else { Ok(()) }
+    }
+
+    fn reset_style(&mut self) -> io::Result<()> {
+        if self.allowed.sgr { self.writer.reset_style() } else { Ok(()) }
+    }
+
+    fn add_style(&mut self, style: Style) -> io::Result<()> {
+        if self.allowed.sgr && !is_disguising(style) { self.writer.add_style(style) } else { Ok(()) }
+    }
+
+    fn remove_style(&mut self, style: Style) -> io::Result<()> {
+        if self.allowed.sgr { self.writer.remove_style(style) } else { Ok(()) }
+    }
+
+    fn cursor_up(&mut self, n: usize) -> io::Result<()> {
+        if self.allowed.cursor { self.writer.cursor_up(n) } else { Ok(()) }
+    }
+
+    fn cursor_down(&mut self, n: usize) -> io::Result<()> {
+        if self.allowed.cursor { self.writer.cursor_down(n) } else { Ok(()) }
+    }
+
+    fn cursor_forward(&mut self, n: usize) -> io::Result<()> {
+        if self.allowed.cursor { self.writer.cursor_forward(n) } else { Ok(()) }
+    }
+
+    fn cursor_backward(&mut self, n: usize) -> io::Result<()> {
+        if self.allowed.cursor { self.writer.cursor_backward(n) } else { Ok(()) }
+    }
+
+    fn set_cursor_position(&mut self, row: usize, column: usize) -> io::Result<()> {
+        if self.allowed.cursor { self.writer.set_cursor_position(row, column) } else { Ok(()) }
+    }
+
+    fn set_cursor_column(&mut self, column: usize) -> io::Result<()> {
+        if self.allowed.cursor { self.writer.set_cursor_column(column) } else { Ok(()) }
+    }
+
+    fn set_cursor_row(&mut self, row: usize) -> io::Result<()> {
+        if self.allowed.cursor { self.writer.set_cursor_row(row) } else { Ok(()) }
+    }
+
+    fn erase_in_display(&mut self, mode: EraseMode) -> io::Result<()> {
+        if self.allowed.erase { self.writer.erase_in_display(mode) } else { Ok(()) }
+    }
+
+    fn erase_in_line(&mut self, mode: EraseMode) -> io::Result<()> {
+        if self.allowed.erase { self.writer.erase_in_line(mode) } else { Ok(()) }
+    }
+
+    fn start_hyperlink(&mut self, uri: &str, id: Option<&str>) -> io::Result<()> {
+        let is_clean = |text: &str| !text.chars().any(char::is_control);
+
+        if self.allowed.hyperlinks && is_clean(uri) && is_clean(id.unwrap_or("")) {
+            self.writer.start_hyperlink(uri, id)
+        } else {
+            Ok(())
+        }
+    }
+
+    fn end_hyperlink(&mut self) -> io::Result<()> {
+        if self.allowed.hyperlinks { self.writer.end_hyperlink() } else { Ok(()) }
+    }
+
+    fn carriage_return(&mut self) -> io::Result<()> {
+        self.writer.carriage_return()
+    }
+
+    fn line_feed(&mut self) -> io::Result<()> {
+        self.writer.line_feed()
+    }
+
+    fn backspace(&mut self) -> io::Result<()> {
+        if self.allowed.cursor { self.writer.backspace() } else { self.replace_control(0x08) }
+    }
+
+    fn tab(&mut self) -> io::Result<()> {
+        self.writer.tab()
+    }
+
+    fn bell(&mut self) -> io::Result<()> {
+        self.replace_control(0x07)
+    }
+
+    fn control(&mut self, byte: u8) -> io::Result<()> {
+        self.replace_control(byte)
+    }
+}
+
+impl<W: Write> Write for Sanitizer<W> {
+    /// Writes raw bytes, which are not sanitized
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.writer.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Allowed, Sanitizer};
+    use io::Write;
+    use prelude::*;
+    use Shim;
+
+    fn sanitize(input: &[u8], allowed: Allowed) -> String {
+        let mut shim = Shim::new(Sanitizer::new(Vec::new(), allowed));
+        shim.write_all(input).unwrap();
+        String::from_utf8(shim.into_inner().finish().unwrap()).unwrap()
+    }
+
+    #[test]
+    fn drops_clipboard_and_titles() {
+        let all = Allowed { sgr: true, cursor: true, erase: true, hyperlinks: true };
+        assert_eq!(sanitize(b"a\x1b]52;c;aGk=\x07b", all), "ab");
+        assert_eq!(sanitize(b"a\x1b]0;title\x1b\\b\x1b]2;title\x07c", all), "abc");
+    }
+
+    #[test]
+    fn drops_device_control_strings() {
+        let all = Allowed { sgr: true, cursor: true, erase: true, hyperlinks: true };
+        assert_eq!(sanitize(b"a\x1bP1$r0m\x1b\\b", all), "ab");
+        assert_eq!(sanitize(b"a\x1bPq#0;2;0;0;0~~\x1b\\b", all), "ab");
+    }
+
+    #[test]
+    fn drops_modes_and_resets() {
+        let all = Allowed { sgr: true, cursor: true, erase: true, hyperlinks: true };
+        assert_eq!(sanitize(b"a\x1b[?1049hb\x1b[?1049l", all), "ab");
+        assert_eq!(sanitize(b"a\x1bcb", all), "ab");
+    }
+
+    #[test]
+    fn drops_disguising_styles() {
+        assert_eq!(sanitize(b"\x1b[8ma\x1b[0m", Allowed::SGR), "a");
+        assert_eq!(sanitize(b"\x1b[10ma\x1b[11mb\x1b[19mc\x1b[20md", Allowed::SGR), "abcd");
+        assert_eq!(sanitize(b"\x1b[1;8ma", Allowed::SGR), "\x1b[1ma\x1b[0m");
+    }
+
+    #[test]
+    fn replaces_control_characters() {
+        assert_eq!(sanitize(b"a\x7fb", Allowed::SGR), "a\u{2421}b");
+        assert_eq!(sanitize(b"a\x07b\x08c", Allowed::SGR), "a\u{2407}b\u{2408}c");
+        assert_eq!(sanitize(b"a\x08b", Allowed::SGR_AND_CURSOR), "a\x08b");
+        assert_eq!(sanitize(b"a\rb\nc\td", Allowed::TEXT), "a\rb\nc\td");
+        assert_eq!(sanitize(b"a\x01b", Allowed::TEXT), "a\u{2401}b");
+    }
+
+    #[test]
+    fn replaces_c1_controls() {
+        assert_eq!(sanitize(b"a\xc2\x9b31mb", Allowed::SGR), "a\u{fffd}31mb");
+        assert_eq!(sanitize(b"a\xc2\x85b", Allowed::SGR), "a\u{fffd}b");
+        assert_eq!(sanitize(b"a\x9b31mb", Allowed::SGR), "a\u{fffd}31mb");
+    }
+
+    #[test]
+    fn text_allows_nothing() {
+        let input = b"\x1b[31ma\x1b[2;3Hb\x1b[2Kc\x1b]8;;http://example.com\x1b\\d\x1b]8;;\x1b\\";
+        assert_eq!(sanitize(input, Allowed::TEXT), "abcd");
+    }
+
+    #[test]
+    fn sgr_allows_colors_only() {
+        let input = b"\x1b[31ma\x1b[2;3Hb\x1b[2Kc\x1b]8;;http://example.com\x1b\\d\x1b]8;;\x1b\\";
+        assert_eq!(sanitize(input, Allowed::SGR), "\x1b[31mabcd\x1b[0m");
+    }
+
+    #[test]
+    fn sgr_and_cursor_allows_erasing() {
+        let input = b"\x1b[31ma\x1b[2;3Hb\x1b[2Kc\x1b]8;;http://example.com\x1b\\d\x1b]8;;\x1b\\";
+        assert_eq!(sanitize(input, Allowed::SGR_AND_CURSOR), "\x1b[31ma\x1b[2;3Hb\x1b[2Kcd\x1b[0m");
+    }
+}
@@ -0,0 +1,471 @@
+use alloc::collections::BTreeSet;
+use io::{self, Write};
+use prelude::*;
+use core::mem;
+use {Color, EraseMode, GraphicState, Style, Terminal};
+
+const TAB_WIDTH: usize = 8;
+
+/// A single character cell of a `Screen`
+#[derive(Clone,Debug,PartialEq,Eq)]
+pub struct Cell {
+    pub ch: char,
+    pub fg: Color,
+    pub bg: Color,
+    pub underline_color: Color,
+    pub styles: BTreeSet<Style>,
+}
+
+impl Cell {
+    /// An empty cell with the given background color
+    fn blank(bg: Color) -> Self {
+        Self {
+            ch: ' ',
+            fg: Color::Default,
+            bg,
+            underline_color: Color::Default,
+            styles: BTreeSet::new(),
+        }
+    }
+}
+
+impl Default for Cell {
+    fn default() -> Self {
+        Cell::blank(Color::Default)
+    }
+}
+
+/// A headless terminal emulator that renders into a grid of cells.
+///
+/// Every character is assumed to occupy a single cell.
+/// Line feeds also return the cursor to the start of the line,
+/// like a tty with `onlcr` enabled does for programs writing `\n`.
+pub struct Screen {
+    width: usize,
+    height: usize,
+    rows: Vec<Vec<Cell>>,
+    scrollback: Vec<Vec<Cell>>,
+    scrollback_limit: usize,
+    cursor_row: usize,
+    cursor_column: usize,
+    /// Set after printing into the last column, the wrap happens on the next print
+    pending_wrap: bool,
+    state: GraphicState,
+    title: String,
+}
+
+impl Screen {
+    /// Creates an empty screen with `width` columns and `height` rows.
+    /// Both dimensions are at least 1.
+    pub fn new(width: usize, height: usize) -> Self {
+        let width = width.max(1);
+        let height = height.max(1);
+
+        Self {
+            width,
+            height,
+            rows: vec![vec![Cell::default(); width]; height],
+            scrollback: Vec::new(),
+            scrollback_limit: 0,
+            cursor_row: 0,
+            cursor_column: 0,
+            pending_wrap: false,
+            state: GraphicState::new(),
+            title: String::new(),
+        }
+    }
+
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    /// Keeps up to `limit` rows that scroll off the top of the screen
+    pub fn set_scrollback_limit(&mut self, limit: usize) {
+        self.scrollback_limit = limit;
+        self.trim_scrollback();
+    }
+
+    /// The rows that scrolled off the top of the screen, oldest first
+    pub fn scrollback(&self) -> &[Vec<Cell>] {
+        &self.scrollback
+    }
+
+    /// The visible grid, top row first
+    pub fn rows(&self) -> &[Vec<Cell>] {
+        &self.rows
+    }
+
+    pub fn cell(&self, row: usize, column: usize) -> Option<&Cell> {
+        self.rows.get(row).and_then(|row| row.get(column))
+    }
+
+    /// The zero-based `(row, column)` of the cursor
+    pub fn cursor_position(&self) -> (usize, usize) {
+        (self.cursor_row, self.cursor_column)
+    }
+
+    /// The last title set by the program
+    pub fn title(&self) -> &str {
+        &self.title
+    }
+
+    /// The text of a visible row, without trailing blanks
+    pub fn row_text(&self, row: usize) -> Option<String> {
+        self.rows.get(row).map(|row| cells_to_text(row))
+    }
+
+    /// The text of all visible rows, without trailing blanks and blank rows
+    pub fn text(&self) -> String {
+        let rows = self.rows.iter().map(|row| cells_to_text(row)).collect::<Vec<_>>();
+        let len = rows.iter().rposition(|row| !row.is_empty()).map_or(0, |last| last + 1);
+
+        rows[..len].join("\n")
+    }
+
+    fn blank(&self) -> Cell {
+        Cell::blank(self.state.bg_color())
+    }
+
+    fn blank_row(&self) -> Vec<Cell> {
+        vec![self.blank(); self.width]
+    }
+
+    fn trim_scrollback(&mut self) {
+        if self.scrollback.len() > self.scrollback_limit {
+            let excess = self.scrollback.len() - self.scrollback_limit;
+            self.scrollback.drain(..excess);
+        }
+    }
+
+    fn scroll_up(&mut self) {
+        let row = self.blank_row();
+        let row = mem::replace(&mut self.rows[0], row);
+        self.rows.rotate_left(1);
+
+        if self.scrollback_limit > 0 {
+            self.scrollback.push(row);
+            self.trim_scrollback();
+        }
+    }
+
+    fn new_line(&mut self) {
+        self.pending_wrap = false;
+        self.cursor_column = 0;
+
+        if self.cursor_row + 1 < self.height {
+            self.cursor_row += 1;
+        } else {
+            self.scroll_up();
+        }
+    }
+
+    fn move_cursor(&mut self, row: usize, column: usize) {
+        self.pending_wrap = false;
+        self.cursor_row = row.min(self.height - 1);
+        self.cursor_column = column.min(self.width - 1);
+    }
+
+    fn erase_cells(&mut self, row: usize, start: usize, end: usize) {
+        let blank = self.blank();
+
+        for cell in &mut self.rows[row][start..end] {
+            *cell = blank.clone();
+        }
+    }
+}
+
+fn cells_to_text(cells: &[Cell]) -> String {
+    let text = cells.iter().map(|cell| cell.ch).collect::<String>();
+    text.trim_end().to_string()
+}
+
+impl Terminal for Screen {
+    fn print(&mut self, ch: char) -> io::Result<()> {
+        if self.pending_wrap {
+            self.new_line();
+        }
+
+        self.rows[self.cursor_row][self.cursor_column] = Cell {
+            ch,
+            fg: self.state.fg_color(),
+            bg: self.state.bg_color(),
+            underline_color: self.state.underline_color(),
+            styles: self.state.styles().clone(),
+        };
+
+        if self.cursor_column + 1 < self.width {
+            self.cursor_column += 1;
+        } else {
+            self.pending_wrap = true;
+        }
+
+        Ok(())
+    }
+
+    fn set_fg_color(&mut self, color: Color) -> io::Result<()> {
+        self.state.set_fg_color(color);
+        Ok(())
+    }
+
+    fn set_bg_color(&mut self, color: Color) -> io::Result<()> {
+        self.state.set_bg_color(color);
+        Ok(())
+    }
+
+    fn set_underline_color(&mut self, color: Color) -> io::Result<()> {
+        self.state.set_underline_color(color);
+        Ok(())
+    }
+
+    fn reset_style(&mut self) -> io::Result<()> {
+        self.state.reset();
+        Ok(())
+    }
+
+    fn add_style(&mut self, style: Style) -> io::Result<()> {
+        self.state.add_style(style);
+        Ok(())
+    }
+
+    fn remove_style(&mut self, style: Style) -> io::Result<()> {
+        self.state.remove_style(style);
+        Ok(())
+    }
+
+    fn cursor_up(&mut self, n: usize) -> io::Result<()> {
+        let (row, column) = (self.cursor_row.saturating_sub(n), self.cursor_column);
+        self.move_cursor(row, column);
+        Ok(())
+    }
+
+    fn cursor_down(&mut self, n: usize) -> io::Result<()> {
+        let (row, column) = (self.cursor_row.saturating_add(n), self.cursor_column);
+        self.move_cursor(row, column);
+        Ok(())
+    }
+
+    fn cursor_forward(&mut self, n: usize) -> io::Result<()> {
+        let (row, column) = (self.cursor_row, self.cursor_column.saturating_add(n));
+        self.move_cursor(row, column);
+        Ok(())
+    }
+
+    fn cursor_backward(&mut self, n: usize) -> io::Result<()> {
+        let (row, column) = (self.cursor_row, self.cursor_column.saturating_sub(n));
+        self.move_cursor(row, column);
+        Ok(())
+    }
+
+    fn set_cursor_position(&mut self, row: usize, column: usize) -> io::Result<()> {
+        self.move_cursor(row, column);
+        Ok(())
+    }
+
+    fn set_cursor_column(&mut self, column: usize) -> io::Result<()> {
+        let row = self.cursor_row;
+        self.move_cursor(row, column);
+        Ok(())
+    }
+
+    fn set_cursor_row(&mut self, row: usize) -> io::Result<()> {
+        let column = self.cursor_column;
+        self.move_cursor(row, column);
+        Ok(())
+    }
+
+    fn erase_in_display(&mut self, mode: EraseMode) -> io::Result<()> {
+        let (row, column, width) = (self.cursor_row, self.cursor_column, self.width);
+
+        match mode {
+            EraseMode::ToEnd => {
+                self.erase_cells(row, column, width);
+                for row in row + 1..self.height {
+                    self.erase_cells(row, 0, width);
+                }
+            },
+            EraseMode::ToStart => {
+                for row in 0..row {
+                    self.erase_cells(row, 0, width);
+                }
+                self.erase_cells(row, 0, column + 1);
+            },
+            EraseMode::All => {
+                for row in 0..self.height {
+                    self.erase_cells(row, 0, width);
+                }
+            },
+        }
+
+        Ok(())
+    }
+
+    fn erase_in_line(&mut self, mode: EraseMode) -> io::Result<()> {
+        let (row, column, width) = (self.cursor_row, self.cursor_column, self.width);
+
+        match mode {
+            EraseMode::ToEnd => self.erase_cells(row, column, width),
+            EraseMode::ToStart => self.erase_cells(row, 0, column + 1),
+            EraseMode::All => self.erase_cells(row, 0, width),
+        }
+
+        Ok(())
+    }
+
+    fn set_title(&mut self, title: &str) -> io::Result<()> {
+        self.title = title.into();
+        Ok(())
+    }
+
+    fn carriage_return(&mut self) -> io::Result<()> {
+        self.pending_wrap = false;
+        self.cursor_column = 0;
+        Ok(())
+    }
+
+    fn line_feed(&mut self) -> io::Result<()> {
+        self.new_line();
+        Ok(())
+    }
+
+    fn backspace(&mut self) -> io::Result<()> {
+        self.pending_wrap = false;
+        self.cursor_column = self.cursor_column.saturating_sub(1);
+        Ok(())
+    }
+
+    fn tab(&mut self) -> io::Result<()> {
+        self.pending_wrap = false;
+        let next_stop = (self.cursor_column / TAB_WIDTH + 1) * TAB_WIDTH;
+        self.cursor_column = next_stop.min(self.width - 1);
+        Ok(())
+    }
+
+    fn query_cursor_position(&self) -> Option<(usize, usize)> {
+        Some(self.cursor_position())
+    }
+
+    fn query_size(&self) -> Option<(usize, usize)> {
+        Some((self.height, self.width))
+    }
+}
+
+impl Write for Screen {
+    /// Output has to go through the `Terminal` methods, raw bytes are ignored
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Cell, Screen};
+    use io::Write;
+    use prelude::*;
+    use {Color, Shim, Style};
+
+    fn render(width: usize, height: usize, input: &str) -> Screen {
+        let mut shim = Shim::new(Screen::new(width, height));
+        shim.write_all(input.as_bytes()).unwrap();
+        shim.into_inner()
+    }
+
+    #[test]
+    fn wraps_at_the_last_column() {
+        let screen = render(4, 3, "abcdef");
+        assert_eq!(screen.text(), "abcd\nef");
+        assert_eq!(screen.cursor_position(), (1, 2));
+    }
+
+    #[test]
+    fn pending_wrap() {
+        let screen = render(4, 3, "abcd");
+        assert_eq!(screen.cursor_position(), (0, 3));
+
+        // A carriage return cancels the wrap
+        let screen = render(4, 3, "abcd\rx");
+        assert_eq!(screen.text(), "xbcd");
+
+        // A line feed after a full line does not leave an empty line
+        let screen = render(4, 3, "abcd\nef");
+        assert_eq!(screen.text(), "abcd\nef");
+    }
+
+    #[test]
+    fn scrolls_into_scrollback() {
+        let mut screen = Screen::new(4, 2);
+        screen.set_scrollback_limit(2);
+        let mut shim = Shim::new(screen);
+        shim.write_all(b"1\n2\n3\n4\n5").unwrap();
+        let screen = shim.into_inner();
+
+        assert_eq!(screen.text(), "4\n5");
+        let scrollback = screen.scrollback().iter().map(|row| row[0].ch).collect::<String>();
+        assert_eq!(scrollback, "23");
+    }
+
+    #[test]
+    fn no_scrollback_by_default() {
+        let screen = render(4, 2, "1\n2\n3");
+        assert_eq!(screen.text(), "2\n3");
+        assert!(screen.scrollback().is_empty());
+    }
+
+    #[test]
+    fn cursor_position_is_clamped() {
+        let screen = render(10, 5, "\x1b[99;99Hx");
+        assert_eq!(screen.cell(4, 9).unwrap().ch, 'x');
+
+        let screen = render(10, 5, "\x1b[3;4H\x1b[9Ax\x1b[99Dy");
+        assert_eq!(screen.text(), "y  x");
+    }
+
+    #[test]
+    fn erase_in_line() {
+        assert_eq!(render(10, 2, "abcdef\x1b[4G\x1b[K").text(), "abc");
+        assert_eq!(render(10, 2, "abcdef\x1b[4G\x1b[1K").text(), "    ef");
+        assert_eq!(render(10, 2, "abcdef\x1b[4G\x1b[2K").text(), "");
+    }
+
+    #[test]
+    fn erase_in_display() {
+        assert_eq!(render(4, 3, "ab\r\ncd\r\nef\x1b[2;2H\x1b[J").text(), "ab\nc");
+        assert_eq!(render(4, 3, "ab\r\ncd\r\nef\x1b[2;1H\x1b[1J").text(), "\n d\nef");
+        assert_eq!(render(4, 3, "ab\r\ncd\r\nef\x1b[2J").text(), "");
+    }
+
+    #[test]
+    fn erase_uses_the_background_color() {
+        let screen = render(4, 1, "ab\x1b[41m\x1b[1G\x1b[K");
+        assert_eq!(screen.cell(0, 3), Some(&Cell { bg: Color::Red, ..Cell::default() }));
+    }
+
+    #[test]
+    fn cells_keep_their_attributes() {
+        let screen = render(4, 1, "\x1b[1;32;44mx\x1b[0my");
+        let cell = screen.cell(0, 0).unwrap();
+
+        assert_eq!((cell.ch, cell.fg, cell.bg), ('x', Color::Green, Color::Blue));
+        assert!(cell.styles.contains(&Style::Bold));
+        assert_eq!(screen.cell(0, 1), Some(&Cell { ch: 'y', ..Cell::default() }));
+    }
+
+    #[test]
+    fn tab_stops() {
+        assert_eq!(render(20, 1, "a\tb").cursor_position(), (0, 9));
+        assert_eq!(render(20, 1, "12345678\tb").text(), "12345678        b");
+        assert_eq!(render(10, 1, "a\t\t\tb").text(), "a        b");
+    }
+
+    #[test]
+    fn title() {
+        assert_eq!(render(4, 1, "\x1b]2;hello\x07").title(), "hello");
+    }
+}
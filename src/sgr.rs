@@ -0,0 +1,281 @@
+use {Color, Style, ALTERNATE_FONTS, UNDERLINE_STYLES};
+use prelude::*;
+
+/// A single attribute change of an SGR sequence (`CSI ... m`)
+#[derive(Copy,Clone,Debug,PartialEq,Eq,Hash)]
+pub enum SgrAttr {
+    Reset,
+    AddStyle(Style),
+    RemoveStyle(Style),
+    /// Sets the foreground color, `Color::Default` restores the default
+    Foreground(Color),
+    /// Sets the background color, `Color::Default` restores the default
+    Background(Color),
+    /// Sets the underline color, `Color::Default` restores the default
+    UnderlineColor(Color),
+}
+
+/// Parses the params of an SGR sequence, each with its colon separated subparams.
+/// Unknown params are skipped.
+pub(crate) fn parse(params: &[Vec<i64>]) -> Vec<SgrAttr> {
+    use Color::*;
+    use Style::*;
+    use self::SgrAttr::*;
+
+    if params.is_empty() {
+        return vec![Reset];
+    }
+
+    let mut attrs = Vec::with_capacity(params.len());
+    let mut params = params.iter();
+
+    while let Some(group) = params.next() {
+        let (param, subparams) = match group.split_first() {
+            Some((&param, subparams)) => (param, subparams),
+            None => continue,
+        };
+
+        let attr = match param {
+            0 => Reset,
+            1 => AddStyle(Bold),
+            2 => AddStyle(Faint),
+            3 => AddStyle(Italic),
+            4 => match subparams.first() {
+                None | Some(1) => AddStyle(Underline),
+                Some(0) => {
+                    remove_underlines(&mut attrs);
+                    continue;
+                },
+                Some(2) => AddStyle(DoubleUnderline),
+                Some(3) => AddStyle(CurlyUnderline),
+                Some(4) => AddStyle(DottedUnderline),
+                Some(5) => AddStyle(DashedUnderline),
+                Some(style) => {
+                    debug!("Unhandled underline style: {}", style);
+                    continue;
+                }
+            },
+            5 => AddStyle(BlinkSlow),
+            6 => AddStyle(BlinkFast),
+            7 => AddStyle(Reverse),
+            8 => AddStyle(Hidden),
+            9 => AddStyle(Crossed),
+            10 => {
+                attrs.extend(ALTERNATE_FONTS.iter().map(|&font| RemoveStyle(font)));
+                continue;
+            },
+            11..=19 => AddStyle(AlternateFont((param - 10) as u8)),
+            20 => AddStyle(Fraktur),
+            21 => AddStyle(DoubleUnderline),
+            22 => {
+                attrs.push(RemoveStyle(Bold));
+                RemoveStyle(Faint)
+            },
+            23 => {
+                attrs.push(RemoveStyle(Italic));
+                RemoveStyle(Fraktur)
+            },
+            24 => {
+                remove_underlines(&mut attrs);
+                continue;
+            },
+            25 => {
+                attrs.push(RemoveStyle(BlinkSlow));
+                RemoveStyle(BlinkFast)
+            },
+            27 => RemoveStyle(Reverse),
+            28 => RemoveStyle(Hidden),
+            29 => RemoveStyle(Crossed),
+            30 => Foreground(Black),
+            31 => Foreground(Red),
+            32 => Foreground(Green),
+            33 => Foreground(Yellow),
+            34 => Foreground(Blue),
+            35 => Foreground(Magenta),
+            36 => Foreground(Cyan),
+            37 => Foreground(White),
+            38 => match parse_extended_color(subparams, &mut params) {
+                Some(color) => Foreground(color),
+                None => continue,
+            },
+            39 => Foreground(Default),
+            40 => Background(Black),
+            41 => Background(Red),
+            42 => Background(Green),
+            43 => Background(Yellow),
+            44 => Background(Blue),
+            45 => Background(Magenta),
+            46 => Background(Cyan),
+            47 => Background(White),
+            48 => match parse_extended_color(subparams, &mut params) {
+                Some(color) => Background(color),
+                None => continue,
+            },
+            49 => Background(Default),
+            51 => AddStyle(Framed),
+            52 => AddStyle(Encircled),
+            53 => AddStyle(Overline),
+            54 => {
+                attrs.push(RemoveStyle(Framed));
+                RemoveStyle(Encircled)
+            },
+            55 => RemoveStyle(Overline),
+            58 => match parse_extended_color(subparams, &mut params) {
+                Some(color) => UnderlineColor(color),
+                None => continue,
+            },
+            59 => UnderlineColor(Default),
+            73 => AddStyle(Superscript),
+            74 => AddStyle(Subscript),
+            75 => {
+                attrs.push(RemoveStyle(Superscript));
+                RemoveStyle(Subscript)
+            },
+            90 => Foreground(BrightBlack),
+            91 => Foreground(BrightRed),
+            92 => Foreground(BrightGreen),
+            93 => Foreground(BrightYellow),
+            94 => Foreground(BrightBlue),
+            95 => Foreground(BrightMagenta),
+            96 => Foreground(BrightCyan),
+            97 => Foreground(BrightWhite),
+            100 => Background(BrightBlack),
+            101 => Background(BrightRed),
+            102 => Background(BrightGreen),
+            103 => Background(BrightYellow),
+            104 => Background(BrightBlue),
+            105 => Background(BrightMagenta),
+            106 => Background(BrightCyan),
+            107 => Background(BrightWhite),
+            _ => {
+                debug!("Unhandled SGR param: {}", param);
+                continue;
+            }
+        };
+
+        attrs.push(attr);
+    }
+
+    attrs
+}
+
+fn remove_underlines(attrs: &mut Vec<SgrAttr>) {
+    attrs.extend(UNDERLINE_STYLES.iter().map(|&style| SgrAttr::RemoveStyle(style)));
+}
+
+/// Parses the arguments of an extended color (SGR 38/48/58), either from the
+/// colon separated `subparams` (`38:2::r:g:b`) or by consuming the params
+/// that belong to the color (`38;2;r;g;b`).
+fn parse_extended_color<'a, I: Iterator<Item=&'a Vec<i64>>>(subparams: &[i64], params: &mut I) -> Option<Color> {
+    let args = if subparams.is_empty() {
+        let mode = params.next().and_then(|group| group.first().cloned());
+        let count = match mode {
+            Some(5) => 1,
+            Some(2) => 3,
+            _ => 0,
+        };
+
+        mode.into_iter()
+            .chain(params.take(count).filter_map(|group| group.first().cloned()))
+            .collect()
+    } else {
+        match *subparams {
+            // ITU T.416 puts a color space id before the channels, which is ignored
+            // along with the tolerance values that may follow them.
+            // Without it, there are only the three channels.
+            [2, _, r, g, b, ..] => vec![2, r, g, b],
+            _ => subparams.to_vec(),
+        }
+    };
+
+    let channel = |value: i64| value.clamp(0, 255) as u8;
+
+    match args[..] {
+        [5, index] => Some(Color::from_index(channel(index))),
+        [2, r, g, b] => Some(Color::Rgb(channel(r), channel(g), channel(b))),
+        _ => {
+            debug!("Unhandled extended color: {:?}", args);
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::SgrAttr::{self, *};
+    use Color::*;
+    use Style::*;
+    use prelude::*;
+    use {Event, Events};
+
+    fn parse(sequence: &str) -> Vec<SgrAttr> {
+        match Events::new(sequence.as_bytes()).next() {
+            Some(Event::Sgr(attrs)) => attrs,
+            other => panic!("not an SGR sequence: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn empty_is_reset() {
+        assert_eq!(parse("\x1b[m"), [Reset]);
+        assert_eq!(parse("\x1b[;1m"), [Reset, AddStyle(Bold)]);
+    }
+
+    #[test]
+    fn semicolon_colors() {
+        assert_eq!(parse("\x1b[38;5;208m"), [Foreground(Indexed(208))]);
+        assert_eq!(parse("\x1b[48;2;255;128;0m"), [Background(Rgb(255, 128, 0))]);
+        assert_eq!(parse("\x1b[58;2;1;2;3m"), [UnderlineColor(Rgb(1, 2, 3))]);
+        assert_eq!(parse("\x1b[38;5;1;1m"), [Foreground(Red), AddStyle(Bold)]);
+    }
+
+    #[test]
+    fn colon_colors_with_color_space() {
+        assert_eq!(parse("\x1b[38:2::255:128:0m"), [Foreground(Rgb(255, 128, 0))]);
+        assert_eq!(parse("\x1b[48:2:0:255:128:0m"), [Background(Rgb(255, 128, 0))]);
+        assert_eq!(parse("\x1b[58:2::1:2:3m"), [UnderlineColor(Rgb(1, 2, 3))]);
+        assert_eq!(parse("\x1b[38:2::1:2:3:0:0m"), [Foreground(Rgb(1, 2, 3))]);
+    }
+
+    #[test]
+    fn colon_colors_without_color_space() {
+        assert_eq!(parse("\x1b[38:2:255:128:0m"), [Foreground(Rgb(255, 128, 0))]);
+        assert_eq!(parse("\x1b[58:2:1:2:3m"), [UnderlineColor(Rgb(1, 2, 3))]);
+        assert_eq!(parse("\x1b[38:5:208m"), [Foreground(Indexed(208))]);
+    }
+
+    #[test]
+    fn colon_colors_do_not_consume_following_params() {
+        assert_eq!(parse("\x1b[38:5:208;1m"), [Foreground(Indexed(208)), AddStyle(Bold)]);
+        assert_eq!(parse("\x1b[38:2:1:2:3;4m"), [Foreground(Rgb(1, 2, 3)), AddStyle(Underline)]);
+    }
+
+    #[test]
+    fn malformed_colors_are_skipped() {
+        assert_eq!(parse("\x1b[38:2:1:2m"), []);
+        assert_eq!(parse("\x1b[38:9:1;1m"), [AddStyle(Bold)]);
+        assert_eq!(parse("\x1b[38;5m"), []);
+    }
+
+    #[test]
+    fn out_of_range_channels_are_clamped() {
+        assert_eq!(parse("\x1b[38;2;300;0;0m"), [Foreground(Rgb(255, 0, 0))]);
+    }
+
+    #[test]
+    fn underline_styles() {
+        assert_eq!(parse("\x1b[4m"), [AddStyle(Underline)]);
+        assert_eq!(parse("\x1b[4:1m"), [AddStyle(Underline)]);
+        assert_eq!(parse("\x1b[4:2m"), [AddStyle(DoubleUnderline)]);
+        assert_eq!(parse("\x1b[4:3m"), [AddStyle(CurlyUnderline)]);
+        assert_eq!(parse("\x1b[4:4m"), [AddStyle(DottedUnderline)]);
+        assert_eq!(parse("\x1b[4:5m"), [AddStyle(DashedUnderline)]);
+        assert_eq!(parse("\x1b[4:9m"), []);
+        assert_eq!(parse("\x1b[4:0m"), parse("\x1b[24m"));
+    }
+
+    #[test]
+    fn semicolon_underline_is_not_a_style() {
+        assert_eq!(parse("\x1b[4;3m"), [AddStyle(Underline), AddStyle(Italic)]);
+    }
+}
@@ -0,0 +1,381 @@
+use prelude::*;
+
+/// The number of color registers, same as xterm
+const COLOR_REGISTERS: usize = 256;
+
+/// The maximum width and height of a decoded image, anything beyond is cut off
+const MAX_SIZE: usize = 4096;
+
+/// The maximum number of pixels of a decoded image, 16 MiB as RGBA
+const MAX_PIXELS: usize = 4 * 1024 * 1024;
+
+/// The VT340 default palette, in percent
+const DEFAULT_PALETTE: [(u8, u8, u8); 16] = [
+    (0, 0, 0),
+    (20, 20, 80),
+    (80, 13, 13),
+    (20, 80, 20),
+    (80, 20, 80),
+    (20, 80, 80),
+    (80, 80, 20),
+    (53, 53, 53),
+    (26, 26, 26),
+    (33, 33, 60),
+    (60, 26, 26),
+    (33, 60, 33),
+    (60, 33, 60),
+    (33, 60, 60),
+    (60, 60, 33),
+    (80, 80, 80),
+];
+
+/// A decoded Sixel image
+#[derive(Clone,Debug,PartialEq,Eq)]
+pub struct SixelImage {
+    pub width: usize,
+    pub height: usize,
+    /// RGBA pixels, row by row
+    pub pixels: Vec<u8>,
+}
+
+impl SixelImage {
+    /// Decodes the payload of a `DCS P1 ; P2 ; P3 q` sequence.
+    ///
+    /// Pixels that are never drawn are transparent if P2 is 1,
+    /// otherwise they take the color of register 0.
+    pub fn decode(params: &[i64], data: &[u8]) -> SixelImage {
+        let mut decoder = Decoder::new();
+        decoder.run(data);
+
+        let transparent = params.get(1) == Some(&1);
+        let background = if transparent { [0; 4] } else { decoder.palette[0] };
+        let mut width = decoder.width.max(decoder.drawn_width);
+        let mut height = decoder.height.max(decoder.rows.len());
+
+        // The raster attributes cost nothing to send, so they only
+        // grow the image as long as it stays within the budget
+        if width * height > MAX_PIXELS {
+            width = decoder.drawn_width;
+            height = decoder.rows.len();
+        }
+
+        let mut pixels = Vec::with_capacity(width * height * 4);
+
+        for y in 0..height {
+            let row = decoder.rows.get(y).map_or(&[][..], |row| &row[..]);
+
+            for x in 0..width {
+                let pixel = row.get(x).cloned().unwrap_or(None).unwrap_or(background);
+                pixels.extend_from_slice(&pixel);
+            }
+        }
+
+        SixelImage { width, height, pixels }
+    }
+
+    /// Encodes the image as an uncompressed PNG
+    pub fn to_png(&self) -> Vec<u8> {
+        let stride = self.width * 4;
+        let mut raw = Vec::with_capacity((stride + 1) * self.height);
+
+        for y in 0..self.height {
+            // Filter type None
+            raw.push(0);
+            raw.extend_from_slice(&self.pixels[y * stride..(y + 1) * stride]);
+        }
+
+        let mut header = Vec::with_capacity(13);
+        header.extend_from_slice(&(self.width as u32).to_be_bytes());
+        header.extend_from_slice(&(self.height as u32).to_be_bytes());
+        // 8 bit RGBA, default compression, filtering and no interlacing
+        header.extend_from_slice(&[8, 6, 0, 0, 0]);
+
+        let mut png = b"\x89PNG\r\n\x1a\n".to_vec();
+        write_chunk(&mut png, b"IHDR", &header);
+        write_chunk(&mut png, b"IDAT", &zlib_stored(&raw));
+        write_chunk(&mut png, b"IEND", &[]);
+
+        png
+    }
+}
+
+fn write_chunk(png: &mut Vec<u8>, kind: &[u8; 4], data: &[u8]) {
+    png.extend_from_slice(&(data.len() as u32).to_be_bytes());
+    let start = png.len();
+    png.extend_from_slice(kind);
+    png.extend_from_slice(data);
+    let crc = crc32(&png[start..]);
+    png.extend_from_slice(&crc.to_be_bytes());
+}
+
+/// Wraps `data` in a zlib stream of uncompressed deflate blocks
+fn zlib_stored(data: &[u8]) -> Vec<u8> {
+    const BLOCK_SIZE: usize = 0xFFFF;
+    let mut zlib = vec![0x78, 0x01];
+    let mut blocks = data.chunks(BLOCK_SIZE).peekable();
+
+    if blocks.peek().is_none() {
+        zlib.extend_from_slice(&[1, 0, 0, 0xFF, 0xFF]);
+    }
+
+    while let Some(block) = blocks.next() {
+        let last = blocks.peek().is_none();
+        let len = block.len() as u16;
+        zlib.push(last as u8);
+        zlib.extend_from_slice(&len.to_le_bytes());
+        zlib.extend_from_slice(&(!len).to_le_bytes());
+        zlib.extend_from_slice(block);
+    }
+
+    zlib.extend_from_slice(&adler32(data).to_be_bytes());
+    zlib
+}
+
+fn crc32(data: &[u8]) -> u32 {
+    let mut crc = !0u32;
+
+    for &byte in data {
+        crc ^= u32::from(byte);
+
+        for _ in 0..8 {
+            let mask = (crc & 1).wrapping_neg();
+            crc = (crc >> 1) ^ (0xEDB8_8320 & mask);
+        }
+    }
+
+    !crc
+}
+
+fn adler32(data: &[u8]) -> u32 {
+    let (mut a, mut b) = (1u32, 0u32);
+
+    for chunk in data.chunks(5552) {
+        for &byte in chunk {
+            a += u32::from(byte);
+            b += a;
+        }
+
+        a %= 65521;
+        b %= 65521;
+    }
+
+    (b << 16) | a
+}
+
+struct Decoder {
+    palette: Vec<[u8; 4]>,
+    color: usize,
+    x: usize,
+    /// The top pixel row of the current sixel row
+    y: usize,
+    rows: Vec<Vec<Option<[u8; 4]>>>,
+    /// The length of the longest row
+    drawn_width: usize,
+    /// The size given by the raster attributes
+    width: usize,
+    height: usize,
+}
+
+impl Decoder {
+    fn new() -> Self {
+        let mut palette = vec![[0, 0, 0, 255]; COLOR_REGISTERS];
+
+        for (entry, &(r, g, b)) in palette.iter_mut().zip(DEFAULT_PALETTE.iter()) {
+            *entry = [percent(r.into()), percent(g.into()), percent(b.into()), 255];
+        }
+
+        Self {
+            palette,
+            color: 0,
+            x: 0,
+            y: 0,
+            rows: Vec::new(),
+            drawn_width: 0,
+            width: 0,
+            height: 0,
+        }
+    }
+
+    fn run(&mut self, mut data: &[u8]) {
+        while let Some((&byte, rest)) = data.split_first() {
+            data = rest;
+
+            match byte {
+                b'?'..=b'~' => self.draw(byte - b'?', 1),
+                b'!' => {
+                    let count = parse_params(&mut data).first().cloned().unwrap_or(1).max(1);
+
+                    if let Some((&byte @ b'?'..=b'~', rest)) = data.split_first() {
+                        data = rest;
+                        self.draw(byte - b'?', count);
+                    }
+                },
+                b'#' => self.select_color(&parse_params(&mut data)),
+                b'"' => {
+                    let params = parse_params(&mut data);
+                    self.width = params.get(2).cloned().unwrap_or(0).min(MAX_SIZE);
+                    self.height = params.get(3).cloned().unwrap_or(0).min(MAX_SIZE);
+                },
+                b'$' => self.x = 0,
+                b'-' => {
+                    self.x = 0;
+                    self.y = self.y.saturating_add(6);
+                },
+                _ => {},
+            }
+        }
+    }
+
+    fn select_color(&mut self, params: &[usize]) {
+        let register = match params.first() {
+            Some(&register) => register % COLOR_REGISTERS,
+            None => return,
+        };
+
+        match params[1..] {
+            [1, h, l, s, ..] => self.palette[register] = hls_to_rgba(h, l, s),
+            [2, r, g, b, ..] => self.palette[register] = [percent(r), percent(g), percent(b), 255],
+            [] => {},
+            _ => debug!("Unhandled Sixel color: {:?}", params),
+        }
+
+        self.color = register;
+    }
+
+    fn draw(&mut self, bits: u8, count: usize) {
+        let start = self.x.min(MAX_SIZE);
+        let end = self.x.saturating_add(count).min(MAX_SIZE);
+        self.x = self.x.saturating_add(count);
+
+        if bits == 0 || start == end {
+            return;
+        }
+
+        let color = self.palette[self.color];
+
+        for bit in 0..6 {
+            let y = self.y.saturating_add(bit);
+
+            if bits & (1 << bit) == 0 || y >= MAX_SIZE {
+                continue;
+            }
+
+            // Keep the drawn area within the budget, by cutting off
+            // whatever would make it wider or taller than it may be
+            let width_limit = MAX_PIXELS / self.rows.len().max(y + 1);
+            let end = end.min(width_limit);
+
+            if start >= end || self.drawn_width > width_limit {
+                continue;
+            }
+
+            self.drawn_width = self.drawn_width.max(end);
+
+            if self.rows.len() <= y {
+                self.rows.resize(y + 1, Vec::new());
+            }
+
+            let row = &mut self.rows[y];
+
+            if row.len() < end {
+                row.resize(end, None);
+            }
+
+            for pixel in &mut row[start..end] {
+                *pixel = Some(color);
+            }
+        }
+    }
+}
+
+/// Parses the `;` separated numeric params at the start of `data`
+fn parse_params(data: &mut &[u8]) -> Vec<usize> {
+    let mut params = Vec::new();
+    let mut param = 0usize;
+    let mut has_param = false;
+
+    while let Some((&byte, rest)) = data.split_first() {
+        match byte {
+            b'0'..=b'9' => {
+                param = param.saturating_mul(10).saturating_add((byte - b'0').into());
+                has_param = true;
+            },
+            b';' => {
+                params.push(param);
+                param = 0;
+                has_param = false;
+            },
+            _ => break,
+        }
+
+        *data = rest;
+    }
+
+    if has_param || !params.is_empty() {
+        params.push(param);
+    }
+
+    params
+}
+
+fn percent(value: usize) -> u8 {
+    (value.min(100) * 255 / 100) as u8
+}
+
+/// Converts a Sixel HLS color, where a hue of 0° is blue, to RGBA
+fn hls_to_rgba(hue: usize, lightness: usize, saturation: usize) -> [u8; 4] {
+    let hue = ((hue % 360 + 240) % 360) as f64 / 60.0;
+    let lightness = lightness.min(100) as f64 / 100.0;
+    let saturation = saturation.min(100) as f64 / 100.0;
+
+    let chroma = (1.0 - (2.0 * lightness - 1.0).abs()) * saturation;
+    let x = chroma * (1.0 - (hue % 2.0 - 1.0).abs());
+    let (r, g, b) = match hue as usize {
+        0 => (chroma, x, 0.0),
+        1 => (x, chroma, 0.0),
+        2 => (0.0, chroma, x),
+        3 => (0.0, x, chroma),
+        4 => (x, 0.0, chroma),
+        _ => (chroma, 0.0, x),
+    };
+    let m = lightness - chroma / 2.0;
+    // Rounds to the nearest value, `f64::round` is not available without `std`
+    let channel = |value: f64| ((value + m) * 255.0 + 0.5) as u8;
+
+    [channel(r), channel(g), channel(b), 255]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{SixelImage, MAX_PIXELS};
+
+    #[test]
+    fn raster_attributes_within_budget() {
+        let image = SixelImage::decode(&[0, 1], b"\"1;1;3;2~");
+        assert_eq!((image.width, image.height), (3, 6));
+        assert_eq!(image.pixels.len(), 3 * 6 * 4);
+    }
+
+    #[test]
+    fn raster_attributes_beyond_budget() {
+        let image = SixelImage::decode(&[0, 1], b"\"1;1;4096;4096~~");
+        assert_eq!((image.width, image.height), (2, 6));
+    }
+
+    #[test]
+    fn huge_color_values() {
+        let image = SixelImage::decode(&[0, 1], b"#0;1;99999999999999999999999;50;50~");
+        assert_eq!((image.width, image.height), (1, 6));
+
+        let image = SixelImage::decode(&[0, 1], b"#99999999999999999999999;2;999;999;999~");
+        assert_eq!(&image.pixels[..4], [255, 255, 255, 255]);
+    }
+
+    #[test]
+    fn drawing_beyond_budget() {
+        let data = "!4096~-".repeat(4096 / 6 + 1);
+        let image = SixelImage::decode(&[0, 1], data.as_bytes());
+        assert!(image.width * image.height <= MAX_PIXELS);
+        assert_eq!(image.pixels.len(), image.width * image.height * 4);
+    }
+}
@@ -0,0 +1,206 @@
+use io::{self, Write};
+use prelude::*;
+use {Color, GraphicState, Shim, Style, Terminal};
+
+/// The target of an OSC 8 hyperlink
+#[derive(Clone,Debug,PartialEq,Eq,Hash)]
+pub struct Hyperlink {
+    pub uri: String,
+    pub id: Option<String>,
+}
+
+/// The complete style of a piece of text
+#[derive(Clone,Debug,Default,PartialEq,Eq)]
+pub struct StyleState {
+    pub graphic: GraphicState,
+    pub hyperlink: Option<Hyperlink>,
+}
+
+/// A run of text sharing the same style
+pub type Span = (StyleState, String);
+
+/// Parses `input` into styled spans
+pub fn parse_spans(input: &[u8]) -> Vec<Span> {
+    let mut shim = Shim::new(SpanCollector::new());
+    shim.write_all(input).expect("collecting spans never fails");
+    shim.into_inner().into_spans()
+}
+
+/// A terminal that collects the output as styled spans.
+///
+/// Adjacent text with identical style ends up in the same span.
+/// Line feeds and tabs are kept as text, other control characters are dropped.
+#[derive(Default)]
+pub struct SpanCollector {
+    spans: Vec<Span>,
+    state: StyleState,
+}
+
+impl SpanCollector {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn spans(&self) -> &[Span] {
+        &self.spans
+    }
+
+    pub fn into_spans(self) -> Vec<Span> {
+        self.spans
+    }
+
+    fn push(&mut self, ch: char) {
+        self.push_str(ch.encode_utf8(&mut [0; 4]));
+    }
+
+    fn push_str(&mut self, new_text: &str) {
+        if let Some(&mut (ref state, ref mut text)) = self.spans.last_mut() {
+            if *state == self.state {
+                text.push_str(new_text);
+                return;
+            }
+        }
+
+        self.spans.push((self.state.clone(), new_text.into()));
+    }
+}
+
+impl Terminal for SpanCollector {
+    fn print(&mut self, ch: char) -> io::Result<()> {
+        self.push(ch);
+        Ok(())
+    }
+
+    fn print_str(&mut self, text: &str) -> io::Result<()> {
+        self.push_str(text);
+        Ok(())
+    }
+
+    fn set_fg_color(&mut self, color: Color) -> io::Result<()> {
+        self.state.graphic.set_fg_color(color);
+        Ok(())
+    }
+
+    fn set_bg_color(&mut self, color: Color) -> io::Result<()> {
+        self.state.graphic.set_bg_color(color);
+        Ok(())
+    }
+
+    fn set_underline_color(&mut self, color: Color) -> io::Result<()> {
+        self.state.graphic.set_underline_color(color);
+        Ok(())
+    }
+
+    fn reset_style(&mut self) -> io::Result<()> {
+        self.state.graphic.reset();
+        Ok(())
+    }
+
+    fn add_style(&mut self, style: Style) -> io::Result<()> {
+        self.state.graphic.add_style(style);
+        Ok(())
+    }
+
+    fn remove_style(&mut self, style: Style) -> io::Result<()> {
+        self.state.graphic.remove_style(style);
+        Ok(())
+    }
+
+    fn start_hyperlink(&mut self, uri: &str, id: Option<&str>) -> io::Result<()> {
+        self.state.hyperlink = Some(Hyperlink {
+            uri: uri.into(),
+            id: id.map(Into::into),
+        });
+        Ok(())
+    }
+
+    fn end_hyperlink(&mut self) -> io::Result<()> {
+        self.state.hyperlink = None;
+        Ok(())
+    }
+
+    fn line_feed(&mut self) -> io::Result<()> {
+        self.push('\n');
+        Ok(())
+    }
+
+    fn tab(&mut self) -> io::Result<()> {
+        self.push('\t');
+        Ok(())
+    }
+
+    fn carriage_return(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Write for SpanCollector {
+    /// Output has to go through the `Terminal` methods, raw bytes are ignored
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{parse_spans, Hyperlink, Span, StyleState};
+    use {Color, GraphicState, Style};
+
+    fn span(graphic: &GraphicState, hyperlink: Option<&str>, text: &str) -> Span {
+        let hyperlink = hyperlink.map(|uri| Hyperlink { uri: uri.into(), id: None });
+        (StyleState { graphic: graphic.clone(), hyperlink }, text.into())
+    }
+
+    #[test]
+    fn plain_text_is_one_span() {
+        let plain = GraphicState::new();
+        assert_eq!(parse_spans(b"ab\r\n\tc\x07d"), [span(&plain, None, "ab\n\tcd")]);
+        assert!(parse_spans(b"\x1b[31m\x1b[0m").is_empty());
+    }
+
+    #[test]
+    fn spans_split_on_style_changes() {
+        let plain = GraphicState::new();
+        let mut red = GraphicState::new();
+        red.set_fg_color(Color::Red);
+
+        assert_eq!(
+            parse_spans(b"a\x1b[31mb\x1b[0mc"),
+            [span(&plain, None, "a"), span(&red, None, "b"), span(&plain, None, "c")],
+        );
+    }
+
+    #[test]
+    fn spans_merge_when_the_style_ends_up_the_same() {
+        let mut bold = GraphicState::new();
+        bold.add_style(Style::Bold);
+
+        assert_eq!(parse_spans(b"\x1b[1ma\x1b[1mb\x1b[22;1mc"), [span(&bold, None, "abc")]);
+        assert_eq!(parse_spans(b"\x1b[1ma\x1b[31m\x1b[39mb"), [span(&bold, None, "ab")]);
+    }
+
+    #[test]
+    fn hyperlinks() {
+        let plain = GraphicState::new();
+        let mut bold = GraphicState::new();
+        bold.add_style(Style::Bold);
+
+        assert_eq!(
+            parse_spans(b"a\x1b]8;;http://a\x1b\\b\x1b[1mc\x1b]8;;\x1b\\d"),
+            [
+                span(&plain, None, "a"),
+                span(&plain, Some("http://a"), "b"),
+                span(&bold, Some("http://a"), "c"),
+                span(&bold, None, "d"),
+            ],
+        );
+
+        let spans = parse_spans(b"\x1b]8;id=x;http://a\x1b\\a\x1b]8;;http://b\x1b\\b");
+        assert_eq!(spans.len(), 2);
+        assert_eq!(spans[0].0.hyperlink, Some(Hyperlink { uri: "http://a".into(), id: Some("x".into()) }));
+    }
+}